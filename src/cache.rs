@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::sha1_hex;
+
+/// Bump whenever the shape of a cached type changes, so old caches are thrown away
+/// instead of being read back with missing fields.
+pub const CACHE_VERSION: u32 = 8;
+
+/// Parse results kept on disk between runs.
+///
+/// Each kind of file (province histories, localisations, ...) has its own cache file mapping
+/// a path to the SHA-1 of the content it was parsed from, so only files that changed since
+/// the last run are parsed again.
+#[derive(Debug, Clone, Default)]
+pub struct ParseCache {
+    directory: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile<T> {
+    version: u32,
+    entries: BTreeMap<String, CacheEntry<T>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry<T> {
+    sha1: String,
+    value: T,
+}
+
+impl ParseCache {
+    pub fn new(directory: impl AsRef<Path>) -> ParseCache {
+        ParseCache { directory: Some(directory.as_ref().to_path_buf()) }
+    }
+
+    /// A cache that never stores anything, every file is parsed
+    pub fn disabled() -> ParseCache {
+        ParseCache { directory: None }
+    }
+
+    fn cache_file(&self, kind: &str) -> Option<PathBuf> {
+        self.directory.as_ref().map(|directory| directory.join(format!("{kind}.json")))
+    }
+
+    fn load<T: DeserializeOwned>(&self, kind: &str) -> BTreeMap<String, CacheEntry<T>> {
+        let cached = self.cache_file(kind)
+            .and_then(|file| fs::read(file).ok())
+            .and_then(|data| serde_json::from_slice::<CacheFile<T>>(&data).ok());
+        match cached {
+            Some(cached) if cached.version == CACHE_VERSION => cached.entries,
+            _ => BTreeMap::new(),
+        }
+    }
+
+    fn save<T: Serialize>(&self, kind: &str, entries: BTreeMap<String, CacheEntry<T>>) {
+        let (Some(directory), Some(file)) = (&self.directory, self.cache_file(kind)) else { return };
+        let cache = CacheFile { version: CACHE_VERSION, entries };
+        let written = fs::create_dir_all(directory)
+            .and_then(|_| serde_json::to_vec(&cache).map_err(std::io::Error::from))
+            .and_then(|data| {
+                // write then rename, so an interrupted run can't leave half a cache behind
+                let partial = file.with_extension("json.partial");
+                fs::write(&partial, data)?;
+                fs::rename(partial, &file)
+            });
+        if let Err(e) = written {
+            println!("Could not write parse cache {}: {e}", file.display());
+        }
+    }
+
+    /// Parses each file with `parse`, reusing the cached result for files whose content is unchanged.
+    ///
+    /// Files are read and parsed on all cores, but results are always in the same order as `files`.
+    pub fn parse_files<T, F>(&self, kind: &str, files: &[PathBuf], parse: F) -> Vec<T>
+    where
+        T: Serialize + DeserializeOwned + Clone + Send + Sync,
+        F: Fn(&[u8]) -> T + Sync,
+    {
+        let cached = self.load::<T>(kind);
+        let parsed: Vec<(String, String, T, bool)> = files.par_iter()
+            .map(|file| {
+                let data = fs::read(file).expect("error reading file");
+                let sha1 = sha1_hex(&data);
+                let key = file.to_string_lossy().to_string();
+                match cached.get(&key) {
+                    Some(entry) if entry.sha1 == sha1 => (key, sha1, entry.value.clone(), true),
+                    _ => (key, sha1, parse(&data), false),
+                }
+            })
+            .collect();
+
+        let reused = parsed.iter().filter(|(_, _, _, reused)| *reused).count();
+        let mut entries = BTreeMap::new();
+        let mut results = Vec::with_capacity(files.len());
+        for (key, sha1, value, _) in parsed {
+            results.push(value.clone());
+            entries.insert(key, CacheEntry { sha1, value });
+        }
+
+        if self.directory.is_some() {
+            println!("Parsed {} {kind} files, {reused} unchanged since the last run", files.len() - reused);
+            // when every file was reused, any extra cached entry belongs to a file that was removed
+            if reused != files.len() || cached.len() != files.len() {
+                self.save(kind, entries);
+            }
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    pub fn test_parse_files_reuses_unchanged() {
+        let root = env::temp_dir().join("anbennar-wiki-test-parse-cache");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("game")).unwrap();
+        let files: Vec<PathBuf> = ["a.txt", "b.txt"].iter().map(|name| root.join("game").join(name)).collect();
+        fs::write(&files[0], "one").unwrap();
+        fs::write(&files[1], "two").unwrap();
+
+        let cache = ParseCache::new(root.join("cache"));
+        let parses = AtomicUsize::new(0);
+        let parse = |data: &[u8]| {
+            parses.fetch_add(1, Ordering::SeqCst);
+            String::from_utf8_lossy(data).to_uppercase()
+        };
+        assert_eq!(cache.parse_files("test", &files, parse), vec!["ONE", "TWO"]);
+        assert_eq!(parses.load(Ordering::SeqCst), 2);
+
+        // nothing changed
+        assert_eq!(cache.parse_files("test", &files, parse), vec!["ONE", "TWO"]);
+        assert_eq!(parses.load(Ordering::SeqCst), 2);
+
+        // only the edited file is parsed again
+        fs::write(&files[1], "three").unwrap();
+        assert_eq!(cache.parse_files("test", &files, parse), vec!["ONE", "THREE"]);
+        assert_eq!(parses.load(Ordering::SeqCst), 3);
+
+        // a cache from another version is ignored
+        fs::write(root.join("cache/test.json"), r#"{"version":0,"entries":{}}"#).unwrap();
+        cache.parse_files("test", &files, parse);
+        assert_eq!(parses.load(Ordering::SeqCst), 5);
+
+        let disabled = ParseCache::disabled();
+        disabled.parse_files("test", &files, parse);
+        assert_eq!(parses.load(Ordering::SeqCst), 7);
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,347 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use deunicode::deunicode;
+use serde::Serialize;
+
+use crate::database::GameDatabase;
+use crate::modifiers::get_modifier;
+use crate::publish::Page;
+use crate::wikitext::Renderer;
+
+/// What changed in the game data between two revisions of the mod
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct Changelog {
+    pub from: String,
+    pub to: String,
+    pub new_tags: Vec<TagChange>,
+    pub removed_tags: Vec<TagChange>,
+    pub idea_modifiers: Vec<ModifierChange>,
+    pub reform_modifiers: Vec<ModifierChange>,
+    pub new_missions: Vec<NewMission>,
+    pub provinces: Vec<ProvinceChange>,
+    pub renamed: Vec<Rename>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct TagChange {
+    pub tag: String,
+    pub name: String,
+}
+
+/// A modifier that was added, removed or given a new value
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ModifierChange {
+    /// What the modifier belongs to, e.g. `Lorentish Ideas: Winemakers` or `Feudal Monarchy`
+    pub source: String,
+    pub modifier: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct NewMission {
+    pub tree: String,
+    pub id: String,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ProvinceChange {
+    pub id: u64,
+    pub name: String,
+    pub old_owner: Option<String>,
+    pub new_owner: Option<String>,
+    pub old_development: u64,
+    pub new_development: u64,
+}
+
+/// A localised name that reads differently now
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Rename {
+    pub key: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// Compares the game data at two revisions; `from` and `to` are only used to label the result
+pub fn diff(from: &str, old: &GameDatabase, to: &str, new: &GameDatabase) -> Changelog {
+    let mut changelog = Changelog { from: from.to_string(), to: to.to_string(), ..Default::default() };
+
+    let old_tags: BTreeMap<&str, &str> = old.countries.iter().map(|c| (c.tag.as_str(), c.name.as_str())).collect();
+    let new_tags: BTreeMap<&str, &str> = new.countries.iter().map(|c| (c.tag.as_str(), c.name.as_str())).collect();
+    for (tag, name) in &new_tags {
+        if !old_tags.contains_key(tag) {
+            changelog.new_tags.push(TagChange { tag: tag.to_string(), name: name.to_string() });
+        }
+    }
+    for (tag, name) in &old_tags {
+        if !new_tags.contains_key(tag) {
+            changelog.removed_tags.push(TagChange { tag: tag.to_string(), name: name.to_string() });
+        }
+    }
+
+    let old_ideas = idea_modifiers(old);
+    let new_ideas = idea_modifiers(new);
+    let idea_keys: BTreeSet<&String> = old_ideas.keys().chain(new_ideas.keys()).collect();
+    for key in idea_keys {
+        let (source, old_modifiers) = old_ideas.get(key).cloned().unwrap_or_default();
+        let (source, new_modifiers) = new_ideas.get(key).cloned().unwrap_or((source, BTreeMap::new()));
+        changelog.idea_modifiers.extend(compare_modifiers(&source, &old_modifiers, &new_modifiers));
+    }
+
+    let reform_ids: BTreeSet<&String> = old.reforms.keys().chain(new.reforms.keys()).collect();
+    for id in reform_ids {
+        let modifiers = |db: &GameDatabase| -> BTreeMap<String, String> {
+            db.reforms.get(id)
+                .map(|reform| reform.modifiers.iter().map(|(k, v)| (k.clone(), String::from_utf8_lossy(v).to_string())).collect())
+                .unwrap_or_default()
+        };
+        let source = new.reforms.get(id).or(old.reforms.get(id))
+            .and_then(|reform| reform.name.clone())
+            .unwrap_or(id.clone());
+        changelog.reform_modifiers.extend(compare_modifiers(&source, &modifiers(old), &modifiers(new)));
+    }
+
+    let old_missions: BTreeSet<&str> = old.missions.iter().flat_map(|tree| tree.missions.iter().map(|m| m.id.as_str())).collect();
+    for tree in &new.missions {
+        for mission in tree.missions.iter().filter(|m| !old_missions.contains(m.id.as_str())) {
+            changelog.new_missions.push(NewMission {
+                tree: tree.name.clone(),
+                id: new.localise(&format!("{}_title", mission.id)).unwrap_or(&mission.id).to_string(),
+                tags: tree.potential_tags.clone(),
+            });
+        }
+    }
+
+    let old_provinces = provinces(old);
+    for (id, (name, owner, development)) in provinces(new) {
+        let (old_owner, old_development) = match old_provinces.get(&id) {
+            Some((_, owner, development)) => (owner.clone(), *development),
+            None => (None, 0),
+        };
+        if old_owner != owner || old_development != development {
+            changelog.provinces.push(ProvinceChange {
+                id,
+                name,
+                old_owner,
+                new_owner: owner,
+                old_development,
+                new_development: development,
+            });
+        }
+    }
+
+    for key in name_keys(new) {
+        if let (Some(old_name), Some(new_name)) = (old.localise(&key), new.localise(&key)) {
+            if old_name != new_name {
+                changelog.renamed.push(Rename { key, old: old_name.to_string(), new: new_name.to_string() });
+            }
+        }
+    }
+
+    changelog
+}
+
+/// The traditions, ambition and ideas of every idea set, by their keys, as (localised name, modifier to value)
+fn idea_modifiers(db: &GameDatabase) -> BTreeMap<String, (String, BTreeMap<String, String>)> {
+    let mut modifiers = BTreeMap::new();
+    for set in db.ideas.idea_sets.values() {
+        let set_name = db.localise(&set.name).unwrap_or(&set.name);
+        modifiers.insert(format!("{}/start", set.name), (format!("{set_name}: Traditions"), set.start.clone()));
+        modifiers.insert(format!("{}/bonus", set.name), (format!("{set_name}: Ambition"), set.bonus.clone()));
+        for idea in &set.ideas {
+            let idea_name = db.localise(&idea.name).unwrap_or(&idea.name);
+            modifiers.insert(format!("{}/{}", set.name, idea.name), (format!("{set_name}: {idea_name}"), idea.effects.clone()));
+        }
+    }
+    modifiers
+}
+
+fn compare_modifiers(source: &str, old: &BTreeMap<String, String>, new: &BTreeMap<String, String>) -> Vec<ModifierChange> {
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    keys.into_iter()
+        .filter(|key| old.get(*key) != new.get(*key))
+        .map(|key| ModifierChange {
+            source: source.to_string(),
+            modifier: key.clone(),
+            old: old.get(key).cloned(),
+            new: new.get(key).cloned(),
+        })
+        .collect()
+}
+
+/// Province id to (name, owner, development)
+fn provinces(db: &GameDatabase) -> BTreeMap<u64, (String, Option<String>, u64)> {
+    let mut provinces = BTreeMap::new();
+    for province in db.map.iter()
+        .flat_map(|superregion| &superregion.regions)
+        .flat_map(|region| &region.areas)
+        .flat_map(|area| &area.provinces) {
+        let (owner, development) = match &province.history {
+            Some(history) => (
+                history.owner.clone(),
+                history.base_tax.unwrap_or(0) + history.base_production.unwrap_or(0) + history.base_manpower.unwrap_or(0),
+            ),
+            None => (None, 0),
+        };
+        provinces.insert(province.id, (province.name.clone(), owner, development));
+    }
+    provinces
+}
+
+/// The localisation keys that name countries, ideas, reforms and provinces
+fn name_keys(db: &GameDatabase) -> BTreeSet<String> {
+    let mut keys = BTreeSet::new();
+    for country in &db.countries {
+        keys.insert(country.tag.clone());
+        keys.insert(format!("{}_ADJ", country.tag));
+    }
+    for set in db.ideas.idea_sets.values() {
+        keys.insert(set.name.clone());
+        keys.extend(set.ideas.iter().map(|idea| idea.name.clone()));
+    }
+    keys.extend(db.reforms.keys().cloned());
+    keys.extend(provinces(db).keys().map(|id| format!("PROV{id}")));
+    keys
+}
+
+fn describe_modifier(key: &str, value: &Option<String>) -> String {
+    let Some(value) = value else { return "none".to_string() };
+    match (get_modifier(&key.to_string()), value.parse::<f32>()) {
+        (Some(modifier), Ok(amount)) => format!("{} {}", modifier.name, modifier.to_human_readable(amount)),
+        _ => format!("{key} = {value}"),
+    }
+}
+
+fn owner(tag: &Option<String>) -> String {
+    match tag {
+        Some(tag) => tag.clone(),
+        None => "none".to_string(),
+    }
+}
+
+impl Changelog {
+    pub fn title(&self) -> String {
+        format!("Changelog/{}..{}", self.from, self.to)
+    }
+
+    /// Names are rendered with `renderer`, as they are on the pages they come from
+    pub fn page(&self, renderer: &Renderer) -> Page {
+        let mut text = format!("Changes to the game files between <code>{}</code> and <code>{}</code>.\n", self.from, self.to);
+        let mut section = |heading: &str, lines: Vec<String>| {
+            if !lines.is_empty() {
+                text += &format!("\n== {heading} ==\n");
+                for line in lines {
+                    text += &format!("* {line}\n");
+                }
+            }
+        };
+        section("New countries", self.new_tags.iter()
+            .map(|c| format!("[[{}]] ({})", deunicode(&c.name), c.tag))
+            .collect());
+        section("Removed countries", self.removed_tags.iter()
+            .map(|c| format!("{} ({})", renderer.render(&c.name), c.tag))
+            .collect());
+        section("National ideas", self.idea_modifiers.iter()
+            .map(|c| format!("{}: {} → {}", renderer.render(&c.source), describe_modifier(&c.modifier, &c.old), describe_modifier(&c.modifier, &c.new)))
+            .collect());
+        section("Government reforms", self.reform_modifiers.iter()
+            .map(|c| format!("{}: {} → {}", renderer.render(&c.source), describe_modifier(&c.modifier, &c.old), describe_modifier(&c.modifier, &c.new)))
+            .collect());
+        section("New missions", self.new_missions.iter()
+            .map(|m| format!("{} ({}, {})", m.id, m.tree, m.tags.join(", ")))
+            .collect());
+        section("Provinces", self.provinces.iter()
+            .map(|p| {
+                let mut changes = vec![];
+                if p.old_owner != p.new_owner {
+                    changes.push(format!("owner {} → {}", owner(&p.old_owner), owner(&p.new_owner)));
+                }
+                if p.old_development != p.new_development {
+                    changes.push(format!("development {} → {}", p.old_development, p.new_development));
+                }
+                format!("{} ({}): {}", renderer.render(&p.name), p.id, changes.join(", "))
+            })
+            .collect());
+        section("Renamed", self.renamed.iter()
+            .map(|r| format!("{} → {} (<code>{}</code>)", renderer.render(&r.old), renderer.render(&r.new), r.key))
+            .collect());
+        text += "\n[[Category:Changelogs]]\n";
+        Page::Full { title: self.title(), text }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    use crate::cache::ParseCache;
+    use crate::paths::{fixture_paths, GamePaths};
+
+    use super::*;
+
+    fn copy_dir(from: &Path, to: &Path) {
+        fs::create_dir_all(to).unwrap();
+        for entry in fs::read_dir(from).unwrap().flatten() {
+            match entry.path().is_dir() {
+                true => copy_dir(&entry.path(), &to.join(entry.file_name())),
+                false => { fs::copy(entry.path(), to.join(entry.file_name())).unwrap(); }
+            }
+        }
+    }
+
+    fn edit(file: &Path, from: &str, to: &str) {
+        let text = fs::read_to_string(file).unwrap();
+        assert!(text.contains(from), "{from} not in {}", file.display());
+        fs::write(file, text.replacen(from, to, 1)).unwrap();
+    }
+
+    #[test]
+    pub fn test_changelog_between_fixture_trees() {
+        let fixtures = fixture_paths();
+        let root = env::temp_dir().join("anbennar-wiki-test-changelog");
+        let _ = fs::remove_dir_all(&root);
+        copy_dir(&fixtures.mod_root, &root);
+        edit(&root.join("common/country_tags/anb_countries.txt"), "A02 = \"countries/Deranne.txt\"\n", "");
+        edit(&root.join("common/ideas/anb_country_ideas.txt"), "cavalry_power = 0.1", "cavalry_power = 0.15");
+        edit(&root.join("common/government_reforms/anb_reforms.txt"), "legitimacy = 1", "legitimacy = 2");
+        edit(&root.join("missions/Lorent_Missions.txt"), "\tlorent_rose_throne = {", "\tlorent_new_mission = {\n\t\tposition = 3\n\t\ttrigger = { }\n\t}\n\tlorent_rose_throne = {");
+        edit(&root.join("history/provinces/3 - Deranne.txt"), "owner = A02", "owner = A01");
+        edit(&root.join("localisation/anb_countries_l_english.yml"), "\"Lorent\"", "\"Kingdom of Lorent\"");
+
+        let old = GameDatabase::load(&fixtures, &ParseCache::disabled());
+        let new = GameDatabase::load(&GamePaths::new(&root, &fixtures.basegame_root), &ParseCache::disabled());
+        let changelog = diff("v1", &old, "v2", &new);
+
+        assert!(changelog.new_tags.is_empty());
+        assert_eq!(changelog.removed_tags, vec![TagChange { tag: "A02".to_string(), name: "Deranne".to_string() }]);
+        assert_eq!(changelog.idea_modifiers, vec![ModifierChange {
+            source: "Lorentish Ideas: Romance & Chivalry".to_string(),
+            modifier: "cavalry_power".to_string(),
+            old: Some("0.1".to_string()),
+            new: Some("0.15".to_string()),
+        }]);
+        assert_eq!(changelog.reform_modifiers.len(), 1);
+        assert_eq!(changelog.reform_modifiers[0].source, "Feudal Monarchy");
+        assert_eq!(changelog.reform_modifiers[0].new.as_deref(), Some("2"));
+        assert_eq!(changelog.new_missions, vec![NewMission {
+            tree: "lorent_missions_1".to_string(),
+            id: "lorent_new_mission".to_string(),
+            tags: vec!["A01".to_string()],
+        }]);
+        assert_eq!(changelog.provinces.len(), 1);
+        assert_eq!(changelog.provinces[0].new_owner.as_deref(), Some("A01"));
+        assert_eq!(changelog.renamed, vec![Rename { key: "A01".to_string(), old: "Lorent".to_string(), new: "Kingdom of Lorent".to_string() }]);
+
+        let Page::Full { title, text } = changelog.page(&Renderer::default()) else { panic!("expected a full page") };
+        assert_eq!(title, "Changelog/v1..v2");
+        assert!(text.contains("\n== National ideas ==\n* Lorentish Ideas: Romance & Chivalry: "));
+        assert!(text.contains("\n== Provinces ==\n* Deranne (3): owner A02 → A01\n"));
+        assert!(text.contains("\n== Removed countries ==\n* Deranne (A02)\n"));
+        assert!(!text.contains("== New countries =="));
+        assert_eq!(serde_json::to_value(&changelog).unwrap()["renamed"][0]["new"], "Kingdom of Lorent");
+        let _ = fs::remove_dir_all(&root);
+    }
+}
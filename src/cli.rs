@@ -0,0 +1,230 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use regex::Regex;
+
+use crate::config::ConfigError;
+use crate::date::Date;
+
+#[derive(Parser, Debug)]
+#[command(name = "anbennar-wiki", about = "Generates pages for the Anbennar gameplay wiki and publishes them")]
+// --all is the only argument that isn't global
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Generator>,
+
+    /// Run every generator; flags are left out without --prior-commit or --since
+    #[arg(long)]
+    pub all: bool,
+
+    /// Config file with the wiki URL, credentials and game paths [default: ./wiki.toml if it exists]
+    #[arg(long, short, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Write pages and uploads to this directory instead of the wiki
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "./dry-run", value_name = "DIRECTORY")]
+    pub dry_run: Option<PathBuf>,
+
+    /// Only generate pages for these country tags, e.g. A01,Z35
+    #[arg(long, global = true, value_delimiter = ',', value_name = "TAGS")]
+    pub tag: Vec<String>,
+
+    /// Only publish pages and files whose title matches this regular expression
+    #[arg(long, global = true, value_name = "REGEX")]
+    pub title_filter: Option<String>,
+
+    /// Use names and descriptions in this language, e.g. french; keys without a translation stay in English
+    /// [default: `language` in the config file, or english]
+    #[arg(long, global = true, value_name = "LANGUAGE")]
+    pub language: Option<String>,
+
+    /// Describe countries as they are at this game date, e.g. 1600.1.1 [default: 1444.11.11]
+    #[arg(long, global = true, value_name = "DATE")]
+    pub date: Option<Date>,
+
+    /// Parse every game file again instead of reusing results from earlier runs
+    #[arg(long, global = true)]
+    pub no_cache: bool,
+
+    /// Mod commit the wiki was last updated from; only flags changed since then are uploaded
+    #[arg(long, global = true, env = "PRIOR_ANB_COMMIT")]
+    pub prior_commit: Option<String>,
+
+    /// Only publish pages and files whose game files changed between this mod commit and HEAD.
+    /// Pages are built from the working tree, but uncommitted edits don't count as changes
+    #[arg(long, global = true, value_name = "COMMIT")]
+    pub since: Option<String>,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum Generator {
+    /// National idea pages
+    Ideas,
+    /// The Countries list and a page per country
+    Countries,
+    /// Upload country flags changed since --prior-commit
+    Flags,
+    /// The racial modifiers page
+    RacialModifiers,
+    /// A page per government type listing its reforms
+    Governments,
+    /// Upload government reform icons
+    GovReformIcons,
+    /// The geographical list of provinces
+    Map,
+    /// A changelog page and JSON file listing what changed in the mod between two commits
+    Changelog {
+        /// The older mod commit, e.g. the last release
+        from: String,
+        /// The newer mod commit
+        #[arg(default_value = "HEAD")]
+        to: String,
+        /// Where to write the changelog as JSON
+        #[arg(long, value_name = "FILE", default_value = "changelog.json")]
+        json: PathBuf,
+    },
+    /// A maintenance page and JSON file listing missing, empty, duplicated and unused localisation keys
+    /// and lines of the localisation files that could not be read
+    LocalisationReport {
+        /// Where to write the report as JSON
+        #[arg(long, value_name = "FILE", default_value = "localisation-report.json")]
+        json: PathBuf,
+    },
+}
+
+impl Generator {
+    pub const ALL: [Generator; 7] = [
+        Generator::Ideas,
+        Generator::Countries,
+        Generator::Flags,
+        Generator::RacialModifiers,
+        Generator::Governments,
+        Generator::GovReformIcons,
+        Generator::Map,
+    ];
+
+    /// Whether this generator builds pages from the game files as they are checked out, rather than
+    /// uploading images or loading other revisions itself
+    pub fn needs_database(&self) -> bool {
+        !matches!(self, Generator::Flags | Generator::GovReformIcons | Generator::Changelog { .. })
+    }
+}
+
+/// Restricts a run to part of the wiki
+#[derive(Debug, Default)]
+pub struct Filters {
+    pub tags: Option<HashSet<String>>,
+    pub titles: Option<Regex>,
+}
+
+impl Filters {
+    pub fn includes_tag(&self, tag: &str) -> bool {
+        self.tags.as_ref().is_none_or(|tags| tags.contains(tag))
+    }
+
+    /// Whether pages covering every tag (like the Countries list) should be skipped
+    pub fn by_tag(&self) -> bool {
+        self.tags.is_some()
+    }
+}
+
+impl Cli {
+    pub fn generators(&self) -> Result<Vec<Generator>, ConfigError> {
+        match (self.all, &self.command) {
+            (true, _) if self.prior_commit.is_none() && self.since.is_none() => {
+                println!("Not uploading flags, they need --prior-commit or --since to find the changed ones");
+                Ok(Generator::ALL.iter().filter(|generator| **generator != Generator::Flags).cloned().collect())
+            }
+            (true, _) => Ok(Generator::ALL.to_vec()),
+            (false, Some(generator)) => Ok(vec![generator.clone()]),
+            (false, None) => Err(ConfigError::NoGenerator),
+        }
+    }
+
+    pub fn filters(&self) -> Result<Filters, ConfigError> {
+        let mut filters = Filters::default();
+        if !self.tag.is_empty() {
+            let mut tags = HashSet::new();
+            for tag in &self.tag {
+                let tag = tag.trim().to_uppercase();
+                if tag.len() != 3 || !tag.chars().all(|c| c.is_ascii_alphanumeric()) {
+                    return Err(ConfigError::InvalidTag(tag));
+                }
+                tags.insert(tag);
+            }
+            filters.tags = Some(tags);
+        }
+        if let Some(pattern) = &self.title_filter {
+            filters.titles = Some(Regex::new(pattern).map_err(ConfigError::InvalidTitleFilter)?);
+        }
+        Ok(filters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_parse_subcommand() {
+        let cli = Cli::try_parse_from(["anbennar-wiki", "countries", "--tag", "A01,z35", "--dry-run"]).unwrap();
+        assert_eq!(cli.generators().unwrap(), vec![Generator::Countries]);
+        assert_eq!(cli.dry_run, Some(PathBuf::from("./dry-run")));
+        let filters = cli.filters().unwrap();
+        assert!(filters.includes_tag("A01"));
+        assert!(filters.includes_tag("Z35"));
+        assert!(!filters.includes_tag("A02"));
+    }
+
+    #[test]
+    pub fn test_parse_all() {
+        let cli = Cli::try_parse_from(["anbennar-wiki", "--all", "--dry-run", "out", "--title-filter", "^Lor", "--language", "german"]).unwrap();
+        assert_eq!(cli.language.as_deref(), Some("german"));
+        assert_eq!(cli.generators().unwrap().len(), 6);
+        assert!(!cli.generators().unwrap().contains(&Generator::Flags));
+        assert_eq!(cli.dry_run, Some(PathBuf::from("out")));
+        let filters = cli.filters().unwrap();
+        assert!(filters.titles.unwrap().is_match("Lorent"));
+    }
+
+    #[test]
+    pub fn test_validation_errors() {
+        let cli = Cli::try_parse_from(["anbennar-wiki"]).unwrap();
+        assert!(matches!(cli.generators(), Err(ConfigError::NoGenerator)));
+        assert!(Cli::try_parse_from(["anbennar-wiki", "--all", "ideas"]).is_err());
+        assert!(Cli::try_parse_from(["anbennar-wiki", "ideas", "--all"]).is_err());
+        let cli = Cli::try_parse_from(["anbennar-wiki", "ideas", "--tag", "A1"]).unwrap();
+        assert!(matches!(cli.filters(), Err(ConfigError::InvalidTag(_))));
+        let cli = Cli::try_parse_from(["anbennar-wiki", "ideas", "--title-filter", "("]).unwrap();
+        assert!(matches!(cli.filters(), Err(ConfigError::InvalidTitleFilter(_))));
+    }
+
+    #[test]
+    pub fn test_parse_changelog() {
+        let cli = Cli::try_parse_from(["anbennar-wiki", "changelog", "v0.9", "--dry-run"]).unwrap();
+        let generators = cli.generators().unwrap();
+        assert_eq!(generators, vec![Generator::Changelog { from: "v0.9".to_string(), to: "HEAD".to_string(), json: PathBuf::from("changelog.json") }]);
+        assert!(!generators[0].needs_database());
+        assert!(!Generator::ALL.contains(&generators[0]));
+    }
+
+    #[test]
+    pub fn test_parse_localisation_report() {
+        let cli = Cli::try_parse_from(["anbennar-wiki", "localisation-report", "--json", "out.json"]).unwrap();
+        let generators = cli.generators().unwrap();
+        assert_eq!(generators, vec![Generator::LocalisationReport { json: PathBuf::from("out.json") }]);
+        assert!(generators[0].needs_database());
+    }
+
+    #[test]
+    pub fn test_parse_since() {
+        let cli = Cli::try_parse_from(["anbennar-wiki", "--all", "--since", "abc123"]).unwrap();
+        assert_eq!(cli.since.as_deref(), Some("abc123"));
+        assert_eq!(cli.generators().unwrap(), Generator::ALL.to_vec());
+        let cli = Cli::try_parse_from(["anbennar-wiki", "countries", "--date", "1600.1.1"]).unwrap();
+        assert_eq!(cli.date, Date::parse("1600.1.1"));
+        assert!(Cli::try_parse_from(["anbennar-wiki", "countries", "--date", "1600"]).is_err());
+    }
+}
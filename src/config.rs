@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::localisation::ColourStyle;
+
+/// Used when `--config` is not given and this file exists in the working directory
+pub const DEFAULT_CONFIG_FILE: &str = "wiki.toml";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    MissingApiUrl,
+    MissingVariable(String),
+    CredentialsFile(PathBuf, String),
+    MissingDirectory(&'static str, PathBuf),
+    NoGenerator,
+    InvalidTag(String),
+    InvalidTitleFilter(regex::Error),
+    MissingPriorCommit,
+    UnknownCommit(String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "could not read {}: {e}", path.display()),
+            ConfigError::Parse(path, e) => write!(f, "invalid config file {}: {e}", path.display()),
+            ConfigError::MissingApiUrl => write!(f, "no wiki API URL: set `api_url` under [wiki] in the config file or the API_URL environment variable"),
+            ConfigError::MissingVariable(name) => write!(f, "environment variable {name} is not set"),
+            ConfigError::CredentialsFile(path, e) => write!(f, "invalid credentials file {}: {e}", path.display()),
+            ConfigError::MissingDirectory(name, path) => write!(f, "{name} {} is not a directory", path.display()),
+            ConfigError::NoGenerator => write!(f, "nothing to do: pass a subcommand or --all (see --help)"),
+            ConfigError::InvalidTag(tag) => write!(f, "invalid country tag {tag:?}: tags are three letters or digits, e.g. A01"),
+            ConfigError::InvalidTitleFilter(e) => write!(f, "invalid --title-filter: {e}"),
+            ConfigError::MissingPriorCommit => write!(f, "uploading flags needs --prior-commit, --since or the PRIOR_ANB_COMMIT environment variable"),
+            ConfigError::UnknownCommit(commit) => write!(f, "{commit} is not a commit in the mod repository"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub wiki: WikiConfig,
+    pub paths: PathsConfig,
+    /// `£token£` to the file shown for it on the wiki, for icons the sprite definitions get wrong or don't have
+    pub icons: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WikiConfig {
+    /// Falls back to the API_URL environment variable
+    pub api_url: Option<String>,
+    pub credentials: Credentials,
+    /// Language of the names and descriptions on this wiki, e.g. `french` for a translated sister wiki
+    pub language: Option<String>,
+    /// `span` (the default) for `<span class="yellow">` styled by the wiki's CSS, or `template` for `{{Color|yellow|...}}`
+    pub colours: ColourStyle,
+}
+
+/// Where the bot password comes from
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "source", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Credentials {
+    Env {
+        #[serde(default = "default_name_variable")]
+        name_variable: String,
+        #[serde(default = "default_password_variable")]
+        password_variable: String,
+    },
+    /// A TOML file with `bot_name` and `bot_password`, kept out of the main config
+    File { path: PathBuf },
+}
+
+fn default_name_variable() -> String {
+    "BOTNAME".to_string()
+}
+
+fn default_password_variable() -> String {
+    "BOTPASS".to_string()
+}
+
+impl Default for Credentials {
+    fn default() -> Self {
+        Credentials::Env {
+            name_variable: default_name_variable(),
+            password_variable: default_password_variable(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    pub mod_root: PathBuf,
+    pub basegame_root: PathBuf,
+    /// Parsed game files are kept here between runs
+    pub cache_directory: PathBuf,
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        PathsConfig {
+            mod_root: PathBuf::from("./anbennar"),
+            basegame_root: PathBuf::from("./basegame"),
+            cache_directory: PathBuf::from("./.parse-cache"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CredentialsFile {
+    bot_name: String,
+    bot_password: String,
+}
+
+impl Config {
+    /// Loads `path`, or `wiki.toml` if it exists, or the defaults.
+    /// Relative paths in the file are relative to the file itself.
+    pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => PathBuf::from(DEFAULT_CONFIG_FILE),
+            None => return Ok(Config::default()),
+        };
+        let data = fs::read_to_string(&path).map_err(|e| ConfigError::Read(path.clone(), e))?;
+        let mut config = Config::parse(&data).map_err(|e| ConfigError::Parse(path.clone(), e))?;
+        if let Some(base) = path.parent() {
+            config.paths.mod_root = base.join(&config.paths.mod_root);
+            config.paths.basegame_root = base.join(&config.paths.basegame_root);
+            config.paths.cache_directory = base.join(&config.paths.cache_directory);
+            if let Credentials::File { path } = &mut config.wiki.credentials {
+                *path = base.join(&path);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn parse(data: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(data)
+    }
+
+    pub fn api_url(&self) -> Result<String, ConfigError> {
+        match &self.wiki.api_url {
+            Some(url) => Ok(url.clone()),
+            None => env::var("API_URL").map_err(|_| ConfigError::MissingApiUrl),
+        }
+    }
+
+    /// (bot name, bot password)
+    pub fn credentials(&self) -> Result<(String, String), ConfigError> {
+        match &self.wiki.credentials {
+            Credentials::Env { name_variable, password_variable } => {
+                let name = env::var(name_variable).map_err(|_| ConfigError::MissingVariable(name_variable.clone()))?;
+                let password = env::var(password_variable).map_err(|_| ConfigError::MissingVariable(password_variable.clone()))?;
+                Ok((name, password))
+            }
+            Credentials::File { path } => {
+                let data = fs::read_to_string(path).map_err(|e| ConfigError::Read(path.clone(), e))?;
+                let file: CredentialsFile = toml::from_str(&data)
+                    .map_err(|e| ConfigError::CredentialsFile(path.clone(), e.to_string()))?;
+                Ok((file.bot_name, file.bot_password))
+            }
+        }
+    }
+
+    pub fn validate_mod_root(&self) -> Result<(), ConfigError> {
+        match self.paths.mod_root.is_dir() {
+            true => Ok(()),
+            false => Err(ConfigError::MissingDirectory("mod root", self.paths.mod_root.clone())),
+        }
+    }
+
+    pub fn validate_basegame_root(&self) -> Result<(), ConfigError> {
+        match self.paths.basegame_root.is_dir() {
+            true => Ok(()),
+            false => Err(ConfigError::MissingDirectory("base game root", self.paths.basegame_root.clone())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_default_config() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.paths.mod_root, PathBuf::from("./anbennar"));
+        assert_eq!(config.wiki.credentials, Credentials::Env { name_variable: "BOTNAME".to_string(), password_variable: "BOTPASS".to_string() });
+    }
+
+    #[test]
+    pub fn test_parse_config() {
+        let config = Config::parse(r#"
+            [wiki]
+            api_url = "https://wiki.example.com/api.php"
+            credentials = { source = "file", path = "secrets.toml" }
+
+            language = "french"
+            colours = "template"
+
+            [paths]
+            mod_root = "../anbennar-eu4"
+
+            [icons]
+            adm = "Administrative power.png"
+        "#).unwrap();
+        assert_eq!(config.wiki.language.as_deref(), Some("french"));
+        assert_eq!(config.wiki.colours, ColourStyle::Template);
+        assert_eq!(config.api_url().unwrap(), "https://wiki.example.com/api.php");
+        assert_eq!(config.wiki.credentials, Credentials::File { path: PathBuf::from("secrets.toml") });
+        assert_eq!(config.paths.mod_root, PathBuf::from("../anbennar-eu4"));
+        assert_eq!(config.paths.basegame_root, PathBuf::from("./basegame"));
+        assert_eq!(config.icons.get("adm").map(|file| file.as_str()), Some("Administrative power.png"));
+    }
+
+    #[test]
+    pub fn test_config_rejects_unknown_keys() {
+        let error = Config::parse("[paths]\nmodroot = \"./anbennar\"\n").unwrap_err();
+        assert!(error.to_string().contains("modroot"));
+    }
+
+    #[test]
+    pub fn test_load_relative_to_config_file() {
+        let root = env::temp_dir().join("anbennar-wiki-test-config");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("wiki.toml"), "[wiki]\ncredentials = { source = \"file\", path = \"secrets.toml\" }\n").unwrap();
+        fs::write(root.join("secrets.toml"), "bot_name = \"Admin@bot\"\nbot_password = \"hunter2\"\n").unwrap();
+        let config = Config::load(Some(&root.join("wiki.toml"))).unwrap();
+        assert_eq!(config.paths.mod_root, root.join("./anbennar"));
+        assert_eq!(config.credentials().unwrap(), ("Admin@bot".to_string(), "hunter2".to_string()));
+        assert!(matches!(config.validate_mod_root(), Err(ConfigError::MissingDirectory(..))));
+        let _ = fs::remove_dir_all(&root);
+    }
+}
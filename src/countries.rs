@@ -1,12 +1,14 @@
 use std::collections::{HashMap, HashSet};
 use std::fs;
 
-use jomini::{JominiDeserialize, TextTape, Windows1252Encoding};
+use jomini::{TextTape, Windows1252Encoding};
 use jomini::text::ValueReader;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
+use crate::cache::ParseCache;
+use crate::date::Date;
 use crate::ideas::IdeaSet;
-use crate::localisation::parse_all_localisations;
+use crate::paths::GamePaths;
 
 #[derive(Debug, Serialize, Default)]
 pub struct Country {
@@ -17,116 +19,391 @@ pub struct Country {
     #[serde(default)]
     pub adjective: String,
     pub ideas: IdeaSet,
+    /// The state at the database's date
     pub history: CountryHistory,
+    pub timeline: CountryTimeline,
+    pub definition: CountryDefinition,
+    /// The tag's file in common/countries, e.g. `common/countries/Lorent.txt`
+    pub definition_file: String,
     pub end_game_tag: bool,
 }
 
-#[derive(Debug, Serialize, JominiDeserialize, Default)]
+/// A common/countries file: how the tag looks on the map and what the AI and name generators pick
+#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq)]
+pub struct CountryDefinition {
+    /// Map colour as RGB
+    pub color: Option<[u8; 3]>,
+    pub graphical_culture: String,
+    /// Idea groups the AI prefers, in order
+    pub historical_idea_groups: Vec<String>,
+    pub historical_units: Vec<String>,
+    /// Name and weight, e.g. `Kylian #6` and 100; negative weights are for female rulers
+    pub monarch_names: Vec<(String, i64)>,
+    pub leader_names: Vec<String>,
+    pub ship_names: Vec<String>,
+    pub army_names: Vec<String>,
+    pub fleet_names: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq)]
 pub struct CountryHistory {
     // pub tag: String,
-    #[jomini(default)]
     pub setup_vision: bool,
-    #[jomini(default)]
     pub government: String,
-    #[jomini(alias = "add_government_reform", duplicated)]
+    /// add_government_reform
     pub government_reforms: Vec<String>,
-    #[jomini(default)]
     pub government_rank: usize,
-    #[jomini(default)]
+    /// A culture key like `high_lorentish`, localised when a page shows it
     pub primary_culture: String,
-    #[jomini(alias = "add_accepted_culture", duplicated)]
+    /// add_accepted_culture
     pub accepted_cultures: Vec<String>,
-    #[jomini(default)]
+    /// A religion key like `regent_court`
     pub religion: String,
-    #[jomini(default)]
     pub technology_group: String,
-    #[jomini(default)]
     pub capital: usize,
-    #[jomini(default)]
     pub fixed_capital: usize,
-    #[jomini(alias = "historical_rival", duplicated)]
+    /// historical_rival
     pub historical_rivals: Vec<String>,
-    #[jomini(alias = "historical_friend", duplicated)]
+    /// historical_friend
     pub historical_friends: Vec<String>,
+    pub monarch: Option<Ruler>,
+    pub heir: Option<Ruler>,
+    pub queen: Option<Ruler>,
+    /// Generals, admirals, explorers and conquistadors still alive
+    pub leaders: Vec<Leader>,
 }
 
-// Returns (TAG, path)
-pub fn parse_country_tags() -> Vec<(String, String)> {
-    let mut tags = Vec::new();
-    let file = fs::read("./anbennar/common/country_tags/anb_countries.txt")
-        .expect("missing country tags file");
-    let file = file.as_slice();
-    let data = std::str::from_utf8(file).unwrap();
-
-    for mut line in data.lines() {
-       line = line.trim();
-        if !line.is_empty() && !line.starts_with('#') {
-            // TODO: a bit messy
-            let tag = &line[..=2];
-            let start = line.find('"');
-            let end = line.rfind('"');
-            match (start, end) {
-                (Some(x), Some(y)) => {
-                    if tag.to_string() == "NPC" {
-                        continue
+/// A `monarch = { ... }`, `heir = { ... }` or `queen = { ... }` block
+#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq)]
+pub struct Ruler {
+    pub name: String,
+    pub dynasty: String,
+    pub adm: usize,
+    pub dip: usize,
+    pub mil: usize,
+    pub birth_date: Option<Date>,
+    /// Empty for the country's primary culture; in Anbennar the culture also decides the ruler's race
+    pub culture: String,
+    pub female: bool,
+    /// add_ruler_personality, add_heir_personality and add_queen_personality after the block
+    pub personalities: Vec<String>,
+}
+
+/// A `leader = { ... }` block
+#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq)]
+pub struct Leader {
+    pub name: String,
+    /// general, admiral, explorer or conquistador
+    pub kind: String,
+    pub fire: usize,
+    pub shock: usize,
+    pub manuever: usize,
+    pub siege: usize,
+    pub death_date: Option<Date>,
+}
+
+/// One change a history file makes, at the top or in a dated block
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum HistoryEffect {
+    SetupVision(bool),
+    Government(String),
+    AddGovernmentReform(String),
+    GovernmentRank(usize),
+    PrimaryCulture(String),
+    AddAcceptedCulture(String),
+    RemoveAcceptedCulture(String),
+    Religion(String),
+    TechnologyGroup(String),
+    Capital(usize),
+    FixedCapital(usize),
+    HistoricalRival(String),
+    HistoricalFriend(String),
+    Monarch(Ruler),
+    Heir(Ruler),
+    Queen(Ruler),
+    Leader(Leader),
+    AddRulerPersonality(String),
+    AddHeirPersonality(String),
+    AddQueenPersonality(String),
+}
+
+/// A history/countries file: the undated effects, then `1444.11.11 = { ... }` blocks in file order
+#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq)]
+pub struct CountryTimeline {
+    pub undated: Vec<HistoryEffect>,
+    pub dated: Vec<(Date, Vec<HistoryEffect>)>,
+}
+
+impl CountryTimeline {
+    /// The country as the game sets it up at `date`: the undated effects, then every block up to and
+    /// including that date in date order
+    pub fn at(&self, date: Date) -> CountryHistory {
+        let mut history = CountryHistory::default();
+        let mut dated: Vec<&(Date, Vec<HistoryEffect>)> = self.dated.iter().filter(|(when, _)| *when <= date).collect();
+        // stable, so blocks for the same date keep their order in the file
+        dated.sort_by_key(|(when, _)| *when);
+        let effects = self.undated.iter().chain(dated.into_iter().flat_map(|(_, effects)| effects));
+        for effect in effects {
+            match effect.clone() {
+                HistoryEffect::SetupVision(setup_vision) => history.setup_vision = setup_vision,
+                HistoryEffect::Government(government) => history.government = government,
+                HistoryEffect::AddGovernmentReform(reform) => {
+                    if !history.government_reforms.contains(&reform) {
+                        history.government_reforms.push(reform);
                     }
-                    tags.push((tag.to_string(), line[x+1..y].to_string()));
-                },
-                _ => {}
+                }
+                HistoryEffect::GovernmentRank(rank) => history.government_rank = rank,
+                HistoryEffect::PrimaryCulture(culture) => history.primary_culture = culture,
+                HistoryEffect::AddAcceptedCulture(culture) => history.accepted_cultures.push(culture),
+                HistoryEffect::RemoveAcceptedCulture(culture) => history.accepted_cultures.retain(|c| *c != culture),
+                HistoryEffect::Religion(religion) => history.religion = religion,
+                HistoryEffect::TechnologyGroup(group) => history.technology_group = group,
+                HistoryEffect::Capital(capital) => history.capital = capital,
+                HistoryEffect::FixedCapital(capital) => history.fixed_capital = capital,
+                HistoryEffect::HistoricalRival(tag) => history.historical_rivals.push(tag),
+                HistoryEffect::HistoricalFriend(tag) => history.historical_friends.push(tag),
+                HistoryEffect::Monarch(monarch) => {
+                    // the heir taking the throne
+                    if history.heir.as_ref().is_some_and(|heir| heir.name == monarch.name && heir.dynasty == monarch.dynasty) {
+                        history.heir = None;
+                    }
+                    history.monarch = Some(monarch);
+                }
+                HistoryEffect::Heir(heir) => history.heir = Some(heir),
+                HistoryEffect::Queen(queen) => history.queen = Some(queen),
+                HistoryEffect::Leader(leader) => history.leaders.push(leader),
+                HistoryEffect::AddRulerPersonality(personality) => add_personality(&mut history.monarch, personality),
+                HistoryEffect::AddHeirPersonality(personality) => add_personality(&mut history.heir, personality),
+                HistoryEffect::AddQueenPersonality(personality) => add_personality(&mut history.queen, personality),
             }
         }
+        history.leaders.retain(|leader| leader.death_date.is_none_or(|death| death > date));
+        history
     }
+}
 
-    tags
+/// Personalities are added to whoever holds the title when the effect runs, and only once
+fn add_personality(ruler: &mut Option<Ruler>, personality: String) {
+    if let Some(ruler) = ruler {
+        if !ruler.personalities.contains(&personality) {
+            ruler.personalities.push(personality);
+        }
+    }
+}
+
+fn read_number(value: &ValueReader<Windows1252Encoding>) -> usize {
+    value.read_scalar().ok().and_then(|v| v.to_u64().ok()).unwrap_or_default() as usize
+}
+
+fn read_date(value: &ValueReader<Windows1252Encoding>) -> Option<Date> {
+    value.read_string().ok().and_then(|date| Date::parse(&date))
+}
+
+fn parse_ruler(value: ValueReader<Windows1252Encoding>) -> Ruler {
+    let mut ruler = Ruler::default();
+    let Ok(block) = value.read_object() else { return ruler };
+    for (key, _op, value) in block.fields() {
+        match key.read_str().as_ref() {
+            "name" => ruler.name = value.read_string().unwrap_or_default(),
+            "dynasty" => ruler.dynasty = value.read_string().unwrap_or_default(),
+            "adm" => ruler.adm = read_number(&value),
+            "dip" => ruler.dip = read_number(&value),
+            "mil" => ruler.mil = read_number(&value),
+            "birth_date" => ruler.birth_date = read_date(&value),
+            "culture" => ruler.culture = value.read_string().unwrap_or_default(),
+            "female" => ruler.female = value.read_scalar().ok().and_then(|v| v.to_bool().ok()).unwrap_or_default(),
+            _ => {}
+        }
+    }
+    ruler
+}
+
+fn parse_leader(value: ValueReader<Windows1252Encoding>) -> Leader {
+    let mut leader = Leader::default();
+    let Ok(block) = value.read_object() else { return leader };
+    for (key, _op, value) in block.fields() {
+        match key.read_str().as_ref() {
+            "name" => leader.name = value.read_string().unwrap_or_default(),
+            "type" => leader.kind = value.read_string().unwrap_or_default(),
+            "fire" => leader.fire = read_number(&value),
+            "shock" => leader.shock = read_number(&value),
+            "manuever" => leader.manuever = read_number(&value),
+            "siege" => leader.siege = read_number(&value),
+            "death_date" => leader.death_date = read_date(&value),
+            _ => {}
+        }
+    }
+    leader
+}
+
+fn parse_history_effects(fields: jomini::text::ObjectReader<Windows1252Encoding>, effects: &mut Vec<HistoryEffect>, dated: &mut Vec<(Date, Vec<HistoryEffect>)>) {
+    for (key, _op, value) in fields.fields() {
+        let string = || value.read_string().unwrap_or_default();
+        let number = || read_number(&value);
+        let key = key.read_str();
+        let effect = match key.as_ref() {
+            "setup_vision" => HistoryEffect::SetupVision(value.read_scalar().ok().and_then(|v| v.to_bool().ok()).unwrap_or_default()),
+            "government" => HistoryEffect::Government(string()),
+            "add_government_reform" => HistoryEffect::AddGovernmentReform(string()),
+            "government_rank" => HistoryEffect::GovernmentRank(number()),
+            "primary_culture" => HistoryEffect::PrimaryCulture(string()),
+            "add_accepted_culture" => HistoryEffect::AddAcceptedCulture(string()),
+            "remove_accepted_culture" => HistoryEffect::RemoveAcceptedCulture(string()),
+            "religion" => HistoryEffect::Religion(string()),
+            "technology_group" => HistoryEffect::TechnologyGroup(string()),
+            "capital" => HistoryEffect::Capital(number()),
+            "fixed_capital" => HistoryEffect::FixedCapital(number()),
+            "historical_rival" => HistoryEffect::HistoricalRival(string()),
+            "historical_friend" => HistoryEffect::HistoricalFriend(string()),
+            "monarch" => HistoryEffect::Monarch(parse_ruler(value)),
+            "heir" => HistoryEffect::Heir(parse_ruler(value)),
+            "queen" => HistoryEffect::Queen(parse_ruler(value)),
+            "leader" => HistoryEffect::Leader(parse_leader(value)),
+            "add_ruler_personality" => HistoryEffect::AddRulerPersonality(string()),
+            "add_heir_personality" => HistoryEffect::AddHeirPersonality(string()),
+            "add_queen_personality" => HistoryEffect::AddQueenPersonality(string()),
+            key => {
+                if let (Some(date), Ok(block)) = (Date::parse(key), value.read_object()) {
+                    let mut block_effects = vec![];
+                    // dates don't nest, anything dated inside a block is ignored
+                    parse_history_effects(block, &mut block_effects, &mut vec![]);
+                    dated.push((date, block_effects));
+                }
+                continue;
+            }
+        };
+        effects.push(effect);
+    }
+}
+
+/// Reads a history/countries file as a timeline, or why it couldn't be read
+pub fn parse_country_timeline(data: &[u8]) -> Result<CountryTimeline, String> {
+    let mut timeline = CountryTimeline::default();
+    let tape = TextTape::from_slice(data).map_err(|e| e.to_string())?;
+    parse_history_effects(tape.windows1252_reader(), &mut timeline.undated, &mut timeline.dated);
+    Ok(timeline)
 }
 
-pub fn parse_history_for_tag(tag: String) -> Option<CountryHistory> {
-    let paths = fs::read_dir("./anbennar/history/countries").expect("Missing country history directory");
-    for path in paths {
-        match path {
-            Ok(file) => {
-                let filename = file.file_name();
-                let filename = String::from_utf8_lossy(filename.as_encoded_bytes());
-                if filename.starts_with(&tag) {
-                    let data = fs::read(file.path()).expect("error reading file");
-                    let actual: CountryHistory = jomini::text::de::from_windows1252_slice(data.as_slice()).unwrap();
-                    return Option::from(actual);
+fn read_strings(value: &ValueReader<Windows1252Encoding>) -> Vec<String> {
+    match value.read_array() {
+        Ok(values) => values.values().filter_map(|value| value.read_string().ok()).collect(),
+        Err(_) => vec![],
+    }
+}
+
+/// Reads a common/countries file
+pub fn parse_country_definition(data: &[u8]) -> CountryDefinition {
+    let mut definition = CountryDefinition::default();
+    let Ok(tape) = TextTape::from_slice(data) else { return definition };
+    for (key, _op, value) in tape.windows1252_reader().fields() {
+        match key.read_str().as_ref() {
+            "color" => {
+                let rgb: Vec<u8> = value.read_array().map(|values| values.values()
+                    .filter_map(|value| value.read_scalar().ok().and_then(|v| v.to_u64().ok()))
+                    .map(|component| component.min(255) as u8)
+                    .collect()).unwrap_or_default();
+                if let [r, g, b] = rgb[..] {
+                    definition.color = Some([r, g, b]);
                 }
             }
+            "graphical_culture" => definition.graphical_culture = value.read_string().unwrap_or_default(),
+            "historical_idea_groups" => definition.historical_idea_groups = read_strings(&value),
+            "historical_units" => definition.historical_units = read_strings(&value),
+            "monarch_names" => {
+                if let Ok(names) = value.read_object() {
+                    definition.monarch_names = names.fields()
+                        .map(|(name, _op, weight)| (name.read_string(), weight.read_scalar().ok().and_then(|v| v.to_i64().ok()).unwrap_or_default()))
+                        .collect();
+                }
+            }
+            "leader_names" => definition.leader_names = read_strings(&value),
+            "ship_names" => definition.ship_names = read_strings(&value),
+            "army_names" => definition.army_names = read_strings(&value),
+            "fleet_names" => definition.fleet_names = read_strings(&value),
             _ => {}
         }
     }
-    None
+    definition
 }
 
-pub fn parse_country_histories() -> HashMap<String, CountryHistory> {
-    let mut histories: HashMap<String, CountryHistory> = HashMap::new();
-    let paths = fs::read_dir("./anbennar/history/countries").expect("Missing country history directory");
-    for path in paths {
-        match path {
-            Ok(file) => {
-                let tag = String::from(file.file_name().to_str().unwrap().split('-').collect::<Vec<&str>>()[0].trim());
-                let data = fs::read(file.path()).expect("error reading file");
-                let history: CountryHistory = jomini::text::de::from_windows1252_slice(data.as_slice()).unwrap();
-                histories.insert(tag, history);
+/// The common/countries file of each tag, keyed by tag. `tags` are from [parse_country_tags].
+pub fn parse_country_definitions(paths: &GamePaths, cache: &ParseCache, tags: &[(String, String)]) -> HashMap<String, CountryDefinition> {
+    let mut found = vec![];
+    let mut files = vec![];
+    for (tag, path) in tags {
+        match paths.resolve(&format!("common/{path}")) {
+            Some(file) => {
+                found.push(tag.clone());
+                files.push(file);
+            }
+            None => println!("Missing common/{path} for {tag}"),
+        }
+    }
+    let parsed = cache.parse_files("country_definitions", &files, parse_country_definition);
+    found.into_iter().zip(parsed).collect()
+}
+
+// Returns (TAG, path)
+pub fn parse_country_tags(paths: &GamePaths) -> Vec<(String, String)> {
+    let mut tags = Vec::new();
+    for file in paths.files_in("common/country_tags") {
+        let file = fs::read(file).expect("error reading country tags file");
+        let data = String::from_utf8_lossy(file.as_slice());
+
+        for mut line in data.lines() {
+           line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                // TODO: a bit messy
+                let tag = &line[..=2];
+                let start = line.find('"');
+                let end = line.rfind('"');
+                if let (Some(x), Some(y)) = (start, end) {
+                    if tag == "NPC" {
+                        continue
+                    }
+                    tags.push((tag.to_string(), line[x+1..y].to_string()));
+                }
             }
-            _ => {}
         }
     }
 
-    histories
+    tags
+}
+
+pub fn parse_country_timelines(paths: &GamePaths, cache: &ParseCache) -> HashMap<String, CountryTimeline> {
+    let mut timelines: HashMap<String, CountryTimeline> = HashMap::new();
+    let files = paths.files_in("history/countries");
+    let parsed = cache.parse_files("country_histories", &files, parse_country_timeline);
+    for (file, timeline) in files.iter().zip(parsed) {
+        let timeline = match timeline {
+            Ok(timeline) => timeline,
+            Err(e) => {
+                println!("Skipping {}: {e}", file.display());
+                continue;
+            }
+        };
+        let tag = String::from(file.file_name().unwrap().to_str().unwrap().split('-').collect::<Vec<&str>>()[0].trim());
+        timelines.insert(tag, timeline);
+    }
+
+    timelines
 }
 
-pub fn parse_countries() -> Vec<Country>{
+/// Every tag with a name, with its history as it is at `date`
+pub fn parse_countries(paths: &GamePaths, cache: &ParseCache, localisations: &HashMap<String, String>, date: Date) -> Vec<Country>{
     let mut country_map: HashMap<String, Country> = HashMap::new();
-    let mut histories = parse_country_histories();
-    let localisations = parse_all_localisations();
-    let end_game_tags = end_game_tags();
+    let mut timelines = parse_country_timelines(paths, cache);
+    let end_game_tags = end_game_tags(paths);
+    let tags = parse_country_tags(paths);
+    let mut definitions = parse_country_definitions(paths, cache, &tags);
 
-    for (tag, _path) in parse_country_tags() {
+    for (tag, path) in tags {
         let mut country = Country::default();
         country.tag = tag.clone();
-        country.history = histories.remove(&tag).unwrap_or_else(|| CountryHistory::default());
+        country.timeline = timelines.remove(&tag).unwrap_or_default();
+        country.history = country.timeline.at(date);
+        country.definition = definitions.remove(&tag).unwrap_or_default();
+        country.definition_file = format!("common/{path}");
 
         if let Some(localisation) = localisations.get(&tag) {
             country.name = localisation.to_string();
@@ -141,12 +418,6 @@ pub fn parse_countries() -> Vec<Country>{
         if let Some(localisation) = localisations.get(&format!("{tag}_ADJ")) {
             country.adjective = localisation.to_string();
         }
-        if let Some(culture) = localisations.get(&country.history.primary_culture) {
-            country.history.primary_culture = culture.to_string();
-        }
-        if let Some(religion) = localisations.get(&country.history.religion) {
-            country.history.religion = religion.to_string();
-        }
         if end_game_tags.contains(&country.tag) {
             country.end_game_tag = true;
         }
@@ -156,10 +427,11 @@ pub fn parse_countries() -> Vec<Country>{
     country_map.into_values().collect()
 }
 
-pub fn end_game_tags() -> HashSet<String> {
+pub fn end_game_tags(paths: &GamePaths) -> HashSet<String> {
     let mut results = HashSet::new();
-    let file = fs::read("./anbennar/common/scripted_triggers/00_scripted_triggers.txt");
-    let data = file.expect("Missing 00_scripted_triggers.txt");
+    let file = paths.resolve("common/scripted_triggers/00_scripted_triggers.txt")
+        .expect("Missing 00_scripted_triggers.txt");
+    let data = fs::read(file).expect("error reading file");
     let tape = TextTape::from_slice(&*data).unwrap();
     let reader = tape.windows1252_reader();
 
@@ -186,9 +458,9 @@ pub fn end_game_tags() -> HashSet<String> {
     results
 }
 
-pub fn formable_tags() -> HashSet<String> {
+pub fn formable_tags(paths: &GamePaths) -> HashSet<String> {
     let mut tags = HashSet::new();
-    let search_paths = vec!["./anbennar/decisions", "./anbennar/events"];
+    let search_paths = vec!["decisions", "events"];
 
     fn sift(mut tags: HashSet<String>, obj: ValueReader<Windows1252Encoding>) -> HashSet<String> {
         if let Ok(inner) = obj.read_object() {
@@ -210,23 +482,17 @@ pub fn formable_tags() -> HashSet<String> {
     }
 
     for path in search_paths {
-        let paths = fs::read_dir(path).expect(format!("Missing {path} directory").as_str());
-        for path in paths {
-            match path {
-                Ok(file) => {
-                    let file = fs::read(file.path()).expect("error reading file");
-                    let tape = TextTape::from_slice(file.as_slice()).unwrap();
-                    let reader = tape.windows1252_reader();
-                    for (_key, _op, value) in reader.fields() {
-                        if let Ok(country_decisions) = value.read_object() {
-                            for (_key, _op, value) in country_decisions.fields() {
-                                let parsed = sift(HashSet::new(), value);
-                                tags.extend(parsed);
-                            }
-                        }
+        for file in paths.files_in(path) {
+            let file = fs::read(file).expect("error reading file");
+            let tape = TextTape::from_slice(file.as_slice()).unwrap();
+            let reader = tape.windows1252_reader();
+            for (_key, _op, value) in reader.fields() {
+                if let Ok(country_decisions) = value.read_object() {
+                    for (_key, _op, value) in country_decisions.fields() {
+                        let parsed = sift(HashSet::new(), value);
+                        tags.extend(parsed);
                     }
-                },
-                _ => {}
+                }
             }
         }
     }
@@ -238,43 +504,157 @@ pub fn formable_tags() -> HashSet<String> {
 #[cfg(test)]
 mod tests {
     use crate::countries::*;
+    use crate::date::START_DATE;
+    use crate::localisation::parse_all_localisations;
 
     #[test]
     pub fn test_parse_country_tags() {
-        parse_country_tags();
+        parse_country_tags(&GamePaths::default());
     }
 
     #[test]
     pub fn test_parse_country_histories() {
-        parse_country_histories();
+        parse_country_timelines(&GamePaths::default(), &ParseCache::disabled());
     }
 
     #[test]
     pub fn test_parse_end_game_tags() {
-        let tags = end_game_tags();
+        let tags = end_game_tags(&GamePaths::default());
         assert!(tags.len() > 0);
         assert!(tags.contains("Z01"));
     }
 
     #[test]
     pub fn test_parse_formable_tags() {
-        let tags = formable_tags();
+        let tags = formable_tags(&GamePaths::default());
         assert!(tags.len() > 0);
         assert!(tags.contains("Z35")); // from decisions
         assert!(tags.contains("Z01")); // from events
     }
 
+    #[test]
+    pub fn test_parse_country_history() {
+        let history = parse_country_timeline(b"government = monarchy\nadd_government_reform = feudalism_reform\nadd_government_reform = autocracy_reform\ngovernment_rank = 2\nsetup_vision = yes\nhistorical_rival = A02\n1444.1.1 = { capital = 1 }\ncapital = 67\n").unwrap().at(START_DATE);
+        assert_eq!(history.government, "monarchy");
+        assert_eq!(history.government_reforms, vec!["feudalism_reform", "autocracy_reform"]);
+        assert_eq!(history.government_rank, 2);
+        assert!(history.setup_vision);
+        assert_eq!(history.historical_rivals, vec!["A02"]);
+        // dated blocks up to the start date apply after everything undated
+        assert_eq!(history.capital, 1);
+        assert!(parse_country_timeline(b"capital = { 1\n").is_err());
+    }
+
+    #[test]
+    pub fn test_country_timeline() {
+        let timeline = parse_country_timeline(b"government = monarchy\nreligion = regent_court\nadd_accepted_culture = derannic\n\
+            1600.1.1 = { religion = corinite }\n\
+            1400.1.1 = { monarch = { name = \"Rean\" dynasty = \"Lorentis\" } heir = { name = \"Kylian\" dynasty = \"Lorentis\" } }\n\
+            1430.5.2 = { monarch = { name = \"Kylian\" dynasty = \"Lorentis\" } remove_accepted_culture = derannic }\n\
+            1430.5.2 = { heir = { name = \"Ruben\" dynasty = \"Lorentis\" } }\n").unwrap();
+        assert_eq!(timeline.undated.len(), 3);
+        assert_eq!(timeline.dated.iter().map(|(date, _)| date.to_string()).collect::<Vec<_>>(), vec!["1600.1.1", "1400.1.1", "1430.5.2", "1430.5.2"]);
+
+        let start = timeline.at(START_DATE);
+        assert_eq!(start.religion, "regent_court");
+        assert_eq!(start.monarch.unwrap().name, "Kylian");
+        assert_eq!(start.heir.unwrap().name, "Ruben");
+        assert!(start.accepted_cultures.is_empty());
+
+        let early = timeline.at(Date::parse("1420.1.1").unwrap());
+        assert_eq!(early.monarch.unwrap().name, "Rean");
+        assert_eq!(early.heir.unwrap().name, "Kylian");
+        assert_eq!(early.accepted_cultures, vec!["derannic"]);
+        assert_eq!(timeline.at(Date::parse("1600.1.1").unwrap()).religion, "corinite");
+    }
+
+    #[test]
+    pub fn test_rulers_and_leaders() {
+        let timeline = parse_country_timeline(b"1400.1.1 = {\n\
+            monarch = { name = \"Rean\" dynasty = \"Lorentis\" adm = 3 dip = 1 mil = 6 birth_date = 1370.2.3 culture = moon_elf }\n\
+            add_ruler_personality = just_personality\n\
+            add_ruler_personality = just_personality\n\
+            add_heir_personality = careful_personality\n\
+            queen = { name = \"Ioriel\" dynasty = \"Silmuna\" female = yes }\n\
+            add_queen_personality = kind_hearted_personality\n\
+            leader = { name = \"Ottrac\" type = general fire = 2 shock = 3 manuever = 1 siege = 0 death_date = 1450.1.1 }\n\
+            leader = { name = \"Adran\" type = admiral fire = 1 shock = 1 manuever = 4 siege = 0 death_date = 1420.1.1 }\n\
+            }\n").unwrap();
+        let history = timeline.at(START_DATE);
+        let monarch = history.monarch.unwrap();
+        assert_eq!((monarch.adm, monarch.dip, monarch.mil), (3, 1, 6));
+        assert_eq!(monarch.birth_date, Date::parse("1370.2.3"));
+        assert_eq!(monarch.culture, "moon_elf");
+        // once only, and the heir personality is dropped as there is no heir
+        assert_eq!(monarch.personalities, vec!["just_personality"]);
+        assert_eq!(history.heir, None);
+        let queen = history.queen.unwrap();
+        assert!(queen.female);
+        assert_eq!(queen.personalities, vec!["kind_hearted_personality"]);
+        // leaders who died before the date are gone
+        assert_eq!(history.leaders.iter().map(|leader| leader.name.as_str()).collect::<Vec<_>>(), vec!["Ottrac"]);
+        assert_eq!(history.leaders[0].kind, "general");
+        assert_eq!(history.leaders[0].shock, 3);
+        assert_eq!(timeline.at(Date::parse("1410.1.1").unwrap()).leaders.len(), 2);
+    }
+
+    #[test]
+    pub fn test_parse_country_definition() {
+        let definition = parse_country_definition(b"graphical_culture = westerngfx\ncolor = { 172 31 52 }\n\
+            historical_idea_groups = { aristocracy_ideas economic_ideas }\nhistorical_units = { western_medieval_infantry }\n\
+            monarch_names = { \"Kylian #6\" = 100 \"Ioriel #0\" = -10 }\nleader_names = { Ottrac \"sil Lorentis\" }\n\
+            ship_names = { Rubyhold }\narmy_names = { \"Army of $PROVINCE$\" }\nfleet_names = { \"Redglade Fleet\" }\n");
+        assert_eq!(definition.color, Some([172, 31, 52]));
+        assert_eq!(definition.graphical_culture, "westerngfx");
+        assert_eq!(definition.historical_idea_groups, vec!["aristocracy_ideas", "economic_ideas"]);
+        assert_eq!(definition.historical_units, vec!["western_medieval_infantry"]);
+        assert_eq!(definition.monarch_names, vec![("Kylian #6".to_string(), 100), ("Ioriel #0".to_string(), -10)]);
+        assert_eq!(definition.leader_names, vec!["Ottrac", "sil Lorentis"]);
+        assert_eq!(definition.ship_names, vec!["Rubyhold"]);
+        assert_eq!(definition.army_names, vec!["Army of $PROVINCE$"]);
+        assert_eq!(definition.fleet_names, vec!["Redglade Fleet"]);
+        assert_eq!(parse_country_definition(b"color = { 1 2 }\n").color, None);
+    }
+
     #[test]
     pub fn test_parse_history_for_tag() {
-        let lorent = parse_history_for_tag(String::from("A01")).unwrap();
+        let timelines = parse_country_timelines(&GamePaths::default(), &ParseCache::disabled());
+        let lorent = timelines["A01"].at(START_DATE);
         assert_eq!(lorent.primary_culture, "high_lorentish");
-        let birzartanses = parse_history_for_tag(String::from("F21")).unwrap();
+        let birzartanses = timelines["F21"].at(START_DATE);
         assert_eq!(birzartanses.accepted_cultures, vec!["bahari", "kuzarami"]);
     }
 
+    #[test]
+    pub fn test_parse_countries_from_fixture_tree() {
+        let paths = crate::paths::fixture_paths();
+        let cache = ParseCache::disabled();
+        let mut countries = parse_countries(&paths, &cache, &parse_all_localisations(&paths, &cache), START_DATE);
+        countries.sort_by(|a, b| a.tag.cmp(&b.tag));
+        let tags: Vec<&str> = countries.iter().map(|c| c.tag.as_str()).collect();
+        // FRA falls through from the base game, but its history is hidden by replace_path
+        assert_eq!(tags, vec!["A01", "A02", "FRA"]);
+        assert_eq!(countries[2].name, "France");
+        assert_eq!(countries[2].history.capital, 0);
+        assert_eq!(countries[0].name, "Lorent");
+        assert_eq!(countries[0].adjective, "Lorentish");
+        assert_eq!(countries[0].history.primary_culture, "high_lorentish");
+        assert_eq!(countries[0].history.government_reforms, vec!["feudalism_reform"]);
+        assert_eq!(countries[0].history.monarch.as_ref().unwrap().personalities, vec!["just_personality"]);
+        assert_eq!(countries[0].history.queen.as_ref().unwrap().culture, "moon_elf");
+        assert_eq!(countries[0].history.leaders.len(), 1);
+        assert_eq!(countries[0].definition.color, Some([172, 31, 52]));
+        assert_eq!(countries[0].definition_file, "common/countries/Lorent.txt");
+        assert_eq!(countries[2].definition.color, Some([20, 50, 210]));
+        assert!(!countries[0].end_game_tag);
+        assert!(countries[1].end_game_tag);
+    }
+
     #[test]
     pub fn test_parse_countries() {
-        let countries = parse_countries();
+        let paths = GamePaths::default();
+        let cache = ParseCache::disabled();
+        let countries = parse_countries(&paths, &cache, &parse_all_localisations(&paths, &cache), START_DATE);
         for country in countries {
             assert_ne!(country.tag, "");
             assert_ne!(country.name, "");
@@ -0,0 +1,283 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+
+use crate::cache::ParseCache;
+use crate::date::{Date, START_DATE};
+use crate::countries::{Country, formable_tags, parse_countries};
+use crate::events::{EventSet, parse_events};
+use crate::governments::{Government, GovernmentReform, parse_government_reforms, parse_governments};
+use crate::ideas::{CountryIdeaSets, parse_ideas};
+use crate::localisation::{DEFAULT_LANGUAGE, merge_localisations, parse_localisation_files, resolve_localisation, ScopeContext, Unresolvable};
+use crate::map::{Area, continent_name, parse_continents, parse_map, parse_province_histories, Province, Region, SuperRegion};
+use crate::missions::{MissionTree, parse_missions};
+use crate::modifiers::{EventModifier, parse_event_modifiers};
+use crate::paths::GamePaths;
+use crate::territory::{territories, Territory};
+use crate::wikitext::Renderer;
+
+/// Everything the generators need, parsed once per run.
+///
+/// Generators only read from this, so the same files are never parsed twice
+/// however many pages are built from them.
+pub struct GameDatabase {
+    /// The language names and descriptions are in
+    pub language: String,
+    /// The game date country histories are evaluated at
+    pub date: Date,
+    /// In `language`, with English for keys that aren't translated
+    pub localisations: HashMap<String, String>,
+    /// Sorted by tag, with localised names and their national ideas attached
+    pub countries: Vec<Country>,
+    pub ideas: CountryIdeaSets,
+    pub governments: Vec<Government>,
+    pub reforms: HashMap<String, GovernmentReform>,
+    pub map: Vec<SuperRegion>,
+    /// Province id to where it is in `map`: the superregion's index, then the region and area ids
+    province_index: HashMap<u64, (usize, String, String)>,
+    /// Province id to continent id
+    pub continents: BTreeMap<u64, String>,
+    /// Tag to the provinces it owns and has cores on at the start
+    pub territories: BTreeMap<String, Territory>,
+    pub missions: Vec<MissionTree>,
+    pub mission_tags: HashSet<String>,
+    pub formable_tags: HashSet<String>,
+    pub events: Vec<EventSet>,
+    pub racial_modifiers: Vec<EventModifier>,
+    /// Turns localised text into wikitext, with icons from the game's sprites
+    pub renderer: Renderer,
+}
+
+impl GameDatabase {
+    #[cfg(test)]
+    pub fn load(paths: &GamePaths, cache: &ParseCache) -> GameDatabase {
+        GameDatabase::load_language(paths, cache, DEFAULT_LANGUAGE)
+    }
+
+    /// Loads the game files with names and descriptions in `language`, e.g. `french`
+    pub fn load_language(paths: &GamePaths, cache: &ParseCache, language: &str) -> GameDatabase {
+        GameDatabase::load_at(paths, cache, language, START_DATE)
+    }
+
+    /// Loads the game files in `language` with countries as they are at `date`
+    pub fn load_at(paths: &GamePaths, cache: &ParseCache, language: &str, date: Date) -> GameDatabase {
+        println!("Loading game files from {}", paths.mod_root.display());
+        let localisation_files = parse_localisation_files(paths, cache);
+        if !localisation_files.iter().any(|file| file.language.as_deref() == Some(language)) {
+            println!("No localisation files in {language}, every name will be in {DEFAULT_LANGUAGE}");
+        }
+        let localisations = merge_localisations(&localisation_files, language);
+        let ideas = parse_ideas(paths);
+
+        let mut countries = parse_countries(paths, cache, &localisations, date);
+        for country in countries.iter_mut() {
+            if let Some(set) = ideas.idea_sets.get(&country.tag) {
+                country.ideas = set.clone();
+            }
+        }
+        countries.sort_by(|a, b| a.tag.cmp(&b.tag));
+
+        let reforms = parse_government_reforms(paths, Some(&localisations))
+            .into_iter()
+            .map(|reform| (reform.id.clone(), reform))
+            .collect();
+
+        let province_histories = parse_province_histories(paths, cache);
+
+        let missions = parse_missions(paths, cache);
+        let map = parse_map(paths, &province_histories, &localisations);
+        let racial_modifiers = match paths.resolve("common/event_modifiers/racial_admin_military.txt") {
+            Some(file) => parse_event_modifiers(fs::read(file).expect("error reading racial modifiers file").as_slice()),
+            None => vec![],
+        };
+
+        GameDatabase {
+            language: language.to_string(),
+            date,
+            countries,
+            ideas,
+            governments: parse_governments(paths),
+            reforms,
+            province_index: index_provinces(&map),
+            map,
+            territories: territories(&province_histories),
+            continents: parse_continents(paths),
+            mission_tags: missions.iter().flat_map(|tree| tree.potential_tags.iter().cloned()).collect(),
+            missions,
+            formable_tags: formable_tags(paths),
+            events: parse_events(paths),
+            racial_modifiers,
+            renderer: Renderer::load(paths, &BTreeMap::new()),
+            localisations,
+        }
+    }
+
+    pub fn localise(&self, key: &str) -> Option<&str> {
+        self.localisations.get(key).map(|value| value.as_str())
+    }
+
+    /// Expands `$KEY$` references and `[Root.GetName]`-style commands in a localised string.
+    /// Missing keys and cycles are printed; commands without a value in `context` become placeholders.
+    pub fn resolve(&self, text: &str, context: &ScopeContext) -> String {
+        let resolved = resolve_localisation(text, &self.localisations, context);
+        for unresolved in resolved.unresolved.iter().filter(|u| u.reason != Unresolvable::NoValue) {
+            println!("Unresolved localisation {unresolved}");
+        }
+        resolved.text
+    }
+
+    /// Localised text as wikitext
+    pub fn render(&self, text: &str) -> String {
+        self.renderer.render(text)
+    }
+
+    pub fn country(&self, tag: &str) -> Option<&Country> {
+        self.countries.binary_search_by(|country| country.tag.as_str().cmp(tag))
+            .ok()
+            .map(|index| &self.countries[index])
+    }
+
+    /// A province with the area, region and superregion it is in
+    pub fn province(&self, id: u64) -> Option<(&Province, &Area, &Region, &SuperRegion)> {
+        let (superregion, region, area) = self.province_index.get(&id)?;
+        let superregion = &self.map[*superregion];
+        let region = superregion.regions.get(region)?;
+        let area = region.areas.get(area)?;
+        Some((area.provinces.get(&id)?, area, region, superregion))
+    }
+
+    /// Every province in a region, in no particular order
+    #[cfg(test)]
+    pub fn provinces(&self) -> impl Iterator<Item = &Province> {
+        self.map.iter()
+            .flat_map(|superregion| superregion.regions.iter())
+            .flat_map(|region| region.areas.iter())
+            .flat_map(|area| area.provinces.iter())
+    }
+
+    /// The mod's name for the continent a province is on
+    pub fn continent_name(&self, id: u64) -> Option<&'static str> {
+        self.continents.get(&id).and_then(|continent| continent_name(continent))
+    }
+
+    /// What a tag starts with, if it owns or has a core on any province
+    pub fn territory(&self, tag: &str) -> Option<&Territory> {
+        self.territories.get(tag)
+    }
+
+    pub fn has_missions(&self, tag: &str) -> bool {
+        self.mission_tags.contains(tag)
+    }
+
+    pub fn is_formable(&self, tag: &str) -> bool {
+        self.formable_tags.contains(tag)
+    }
+}
+
+fn index_provinces(map: &[SuperRegion]) -> HashMap<u64, (usize, String, String)> {
+    let mut index = HashMap::new();
+    for (position, superregion) in map.iter().enumerate() {
+        for region in &superregion.regions {
+            for area in &region.areas {
+                for province in &area.provinces {
+                    index.insert(province.id, (position, region.id.clone(), area.id.clone()));
+                }
+            }
+        }
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::paths::fixture_paths;
+
+    use super::*;
+
+    #[test]
+    pub fn test_load_fixture_database() {
+        let db = GameDatabase::load(&fixture_paths(), &ParseCache::disabled());
+        let tags: Vec<&str> = db.countries.iter().map(|c| c.tag.as_str()).collect();
+        assert_eq!(tags, vec!["A01", "A02", "FRA"]);
+
+        let lorent = db.country("A01").unwrap();
+        assert_eq!(lorent.name, "Lorent");
+        assert_eq!(lorent.ideas.name, "A01_ideas");
+        assert_eq!(lorent.history.monarch.as_ref().map(|monarch| monarch.name.as_str()), Some("Kylian VI"));
+        assert_eq!(lorent.history.government_reforms, vec!["feudalism_reform"]);
+        assert_eq!(db.localise(&lorent.ideas.name), Some("Lorentish Ideas"));
+        assert!(db.country("B01").is_none());
+
+        assert!(db.has_missions("A01"));
+        assert!(!db.has_missions("A02"));
+        assert!(db.is_formable("A02"));
+        assert_eq!(db.missions[0].missions.len(), 2);
+        assert_eq!(db.missions[0].name, "lorent_missions_1");
+        assert_eq!(db.missions[0].potential_tags, vec!["A01"]);
+        assert_eq!(db.events[0].events[0].id, "lorent.1");
+
+        assert_eq!(db.governments[0].reform_levels.len(), 2);
+        assert_eq!(db.reforms["feudalism_reform"].name.as_deref(), Some("Feudal Monarchy"));
+        assert_eq!(db.map.len(), 1);
+        assert_eq!(db.continents.get(&3).map(|c| c.as_str()), Some("europe"));
+        assert_eq!(db.continent_name(3), Some("Cannor"));
+        let (province, area, region, superregion) = db.province(2).unwrap();
+        assert_eq!((province.name.as_str(), area.id.as_str(), region.id.as_str(), superregion.id.as_str()), ("Rewanwood", "lorent_area", "lencenor_region", "west_cannor_superregion"));
+        assert!(db.province(999).is_none());
+        assert_eq!(db.provinces().count(), 3);
+        assert_eq!(db.territory("A01").map(|territory| territory.owned.len()), Some(2));
+        assert!(db.territory("FRA").is_none());
+        assert_eq!(db.racial_modifiers.len(), 3);
+    }
+
+    #[test]
+    pub fn test_load_database_at_date() {
+        let db = GameDatabase::load_at(&fixture_paths(), &ParseCache::disabled(), DEFAULT_LANGUAGE, Date::parse("1600.1.1").unwrap());
+        let lorent = db.country("A01").unwrap();
+        assert_eq!(db.date.to_string(), "1600.1.1");
+        assert_eq!(lorent.history.government_reforms, vec!["feudalism_reform", "autocracy_reform"]);
+        assert_eq!(lorent.history.religion, "corinite");
+    }
+
+    #[test]
+    pub fn test_load_translated_database() {
+        let db = GameDatabase::load_language(&fixture_paths(), &ParseCache::disabled(), "french");
+        assert_eq!(db.language, "french");
+        assert_eq!(db.country("A02").unwrap().name, "Déranne");
+        assert_eq!(db.localise(&db.country("A01").unwrap().history.primary_culture), Some("Haut-lorentais"));
+        assert_eq!(db.localise("A01_ideas"), Some("Idées lorentaises"));
+        // untranslated keys fall back to English
+        assert_eq!(db.localise("A01_winemakers"), Some("Winemakers"));
+        assert_eq!(db.reforms["feudalism_reform"].name.as_deref(), Some("Feudal Monarchy"));
+    }
+
+    /// Loads the full mod tree on one thread and then on every core, e.g.
+    /// `cargo test --release bench_parallel_load -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    pub fn bench_parallel_load() {
+        let paths = GamePaths::default();
+        let timed = |threads: usize| -> (GameDatabase, Duration) {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            let start = Instant::now();
+            let db = pool.install(|| GameDatabase::load(&paths, &ParseCache::disabled()));
+            (db, start.elapsed())
+        };
+
+        let (sequential, sequential_time) = timed(1);
+        let (parallel, parallel_time) = timed(rayon::current_num_threads());
+        println!("1 thread: {sequential_time:?}, {} threads: {parallel_time:?} ({:.1}x)",
+                 rayon::current_num_threads(), sequential_time.as_secs_f64() / parallel_time.as_secs_f64());
+
+        // the merge must not depend on how the work was scheduled
+        assert_eq!(sequential.localisations, parallel.localisations);
+        assert_eq!(serde_json::to_string(&sequential.countries).unwrap(), serde_json::to_string(&parallel.countries).unwrap());
+        assert_eq!(serde_json::to_string(&sequential.missions).unwrap(), serde_json::to_string(&parallel.missions).unwrap());
+        assert_eq!(sequential.mission_tags, parallel.mission_tags);
+        let event_ids = |db: &GameDatabase| -> Vec<String> {
+            db.events.iter().flat_map(|set| set.events.iter().map(|event| event.id.clone())).collect()
+        };
+        assert_eq!(event_ids(&sequential), event_ids(&parallel));
+    }
+}
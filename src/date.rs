@@ -0,0 +1,60 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// The mod's 1444 bookmark, where every game starts unless another date is chosen
+pub const START_DATE: Date = Date { year: 1444, month: 11, day: 11 };
+
+/// A game date like `1444.11.11`, as used for dated blocks in history files
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Date {
+    pub year: i32,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Date {
+    /// `1444.11.11`; None for anything else, like a history key that isn't a date
+    pub fn parse(text: &str) -> Option<Date> {
+        let mut parts = text.trim().split('.');
+        let year = parts.next()?.parse().ok()?;
+        let month = parts.next()?.parse().ok()?;
+        let day = parts.next()?.parse().ok()?;
+        match parts.next() {
+            None if (1..=12).contains(&month) && (1..=31).contains(&day) => Some(Date { year, month, day }),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Date {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.year, self.month, self.day)
+    }
+}
+
+impl FromStr for Date {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Date, String> {
+        Date::parse(text).ok_or_else(|| format!("{text:?} is not a date like 1444.11.11"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_parse_dates() {
+        assert_eq!(Date::parse("1444.11.11"), Some(START_DATE));
+        assert_eq!(Date::parse("1.1.1"), Some(Date { year: 1, month: 1, day: 1 }));
+        assert_eq!(Date::parse("1444.13.1"), None);
+        assert_eq!(Date::parse("capital"), None);
+        assert_eq!(Date::parse("1444.11.11.1"), None);
+        assert!(Date::parse("1444.2.1") < Date::parse("1444.11.1"));
+        assert_eq!(START_DATE.to_string(), "1444.11.11");
+        assert!("1444.11".parse::<Date>().is_err());
+    }
+}
@@ -1,41 +1,42 @@
 use std::fs;
 
 use jomini::JominiDeserialize;
+use rayon::prelude::*;
+
+use crate::paths::GamePaths;
 
 #[derive(Clone, Debug, JominiDeserialize)]
 pub struct EventSet {
     //namespace: Option<String>, // there are files with multiple namespaces
     #[jomini(alias = "country_event", duplicated)]
-    events: Vec<Event>,
+    pub events: Vec<Event>,
 }
 
 #[derive(Clone, Debug, JominiDeserialize)]
 pub struct Event {
-    id: String,
+    pub id: String,
     #[jomini(take_last)]
-    title: String,
+    pub title: String,
     // picture: String, // can be an object
     // desc: String, // can be an object
 }
 
-pub fn parse_events() -> Vec<EventSet> {
-    let mut results = Vec::with_capacity(100);
-    let paths = fs::read_dir("./anbennar/events").expect("Missing events directory");
-    for path in paths {
-        match path {
-            Ok(file) => {
-                let data = fs::read(file.path()).expect("error reading file");
-                let actual: EventSet = jomini::text::de::from_windows1252_slice(data.as_slice()).unwrap();
-                if actual.events.len() < 1 {
-                    continue;
+pub fn parse_events(paths: &GamePaths) -> Vec<EventSet> {
+    let parsed: Vec<Option<EventSet>> = paths.files_in("events").par_iter()
+        .map(|file| {
+            let data = fs::read(file).expect("error reading file");
+            match jomini::text::de::from_windows1252_slice::<EventSet>(data.as_slice()) {
+                Ok(actual) if actual.events.is_empty() => None,
+                Ok(actual) => Some(actual),
+                Err(e) => {
+                    println!("Skipping {}: {e}", file.display());
+                    None
                 }
-                results.push(actual);
             }
-            _ => {}
-        }
-    }
+        })
+        .collect();
 
-    results
+    parsed.into_iter().flatten().collect()
 }
 
 
@@ -45,7 +46,7 @@ mod tests {
 
     #[test]
     pub fn test_events_parse() {
-        let event_sets = parse_events();
+        let event_sets = parse_events(&GamePaths::default());
         assert!(event_sets.len() > 0);
     }
 }
\ No newline at end of file
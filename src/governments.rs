@@ -3,8 +3,8 @@ use std::fs;
 
 use jomini::{Scalar, TextTape};
 
-use crate::localisation::parse_all_localisations;
 use crate::modifiers::get_modifier;
+use crate::paths::GamePaths;
 use crate::utils::jsonify;
 
 #[derive(Debug, Default)]
@@ -95,18 +95,12 @@ pub fn parse_government(data: &[u8]) -> Vec<Government> {
     governments
 }
 
-pub fn parse_governments() -> Vec<Government> {
+pub fn parse_governments(paths: &GamePaths) -> Vec<Government> {
     let mut governments = vec![];
-    let paths = fs::read_dir("./anbennar/common/governments").expect("Missing governments directory");
-    for path in paths {
-        match path {
-            Ok(file) => {
-                let data = fs::read(file.path()).expect("error reading file");
-                let parsed = parse_government(data.as_slice());
-                governments.extend(parsed);
-            }
-            _ => {}
-        }
+    for file in paths.files_in("common/governments") {
+        let data = fs::read(file).expect("error reading file");
+        let parsed = parse_government(data.as_slice());
+        governments.extend(parsed);
     }
 
     governments
@@ -166,18 +160,12 @@ pub fn parse_government_reform_file(data: &[u8], localisations: Option<&HashMap<
     reforms
 }
 
-pub fn parse_government_reforms(localisations: Option<&HashMap<String, String>>) -> Vec<GovernmentReform> {
+pub fn parse_government_reforms(paths: &GamePaths, localisations: Option<&HashMap<String, String>>) -> Vec<GovernmentReform> {
     let mut reforms = vec![];
-    let paths = fs::read_dir("./anbennar/common/government_reforms").expect("Missing government reforms directory");
-    for path in paths {
-        match path {
-            Ok(file) => {
-                let data = fs::read(file.path()).expect("error reading file");
-                let parsed = parse_government_reform_file(data.as_slice(), localisations);
-                reforms.extend(parsed);
-            }
-            _ => {}
-        }
+    for file in paths.files_in("common/government_reforms") {
+        let data = fs::read(file).expect("error reading file");
+        let parsed = parse_government_reform_file(data.as_slice(), localisations);
+        reforms.extend(parsed);
     }
 
     reforms
@@ -187,39 +175,30 @@ pub fn parse_government_reforms(localisations: Option<&HashMap<String, String>>)
 mod tests {
     use std::fs;
 
+    use crate::localisation::parse_all_localisations;
+
     use super::*;
 
     #[test]
     pub fn test_governments_parse() {
-        let paths = fs::read_dir("./anbennar/common/governments").expect("Missing governments directory");
-        for path in paths {
-            match path {
-                Ok(file) => {
-                    let data = fs::read(file.path()).expect("error reading file");
-                    let governments = parse_government(data.as_slice());
-                }
-                _ => {}
-            }
+        for file in GamePaths::default().files_in("common/governments") {
+            let data = fs::read(file).expect("error reading file");
+            let governments = parse_government(data.as_slice());
         }
     }
 
     #[test]
     pub fn test_government_reform_parse() {
-        let paths = fs::read_dir("./anbennar/common/government_reforms").expect("Missing government reforms directory");
-        let localisations = parse_all_localisations();
-        for path in paths {
-            match path {
-                Ok(file) => {
-                    let data = fs::read(file.path()).expect("error reading file");
-                    let reforms = parse_government_reform_file(data.as_slice(), Some(&localisations));
-                    for reform in reforms {
-                        assert!(!reform.id.is_empty());
-                        for (_, modifier) in reform.modifiers {
-                            Scalar::new(modifier.as_slice());
-                        }
-                    }
+        let paths = GamePaths::default();
+        let localisations = parse_all_localisations(&paths, &crate::cache::ParseCache::disabled());
+        for file in paths.files_in("common/government_reforms") {
+            let data = fs::read(file).expect("error reading file");
+            let reforms = parse_government_reform_file(data.as_slice(), Some(&localisations));
+            for reform in reforms {
+                assert!(!reform.id.is_empty());
+                for (_, modifier) in reform.modifiers {
+                    Scalar::new(modifier.as_slice());
                 }
-                _ => {}
             }
         }
     }
@@ -1,30 +1,50 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use std::path::Path;
 
 use jomini::TextTape;
 
-use crate::utils::read_all_files_in_path;
+use crate::paths::GamePaths;
 
-#[derive(Debug, Default)]
+/// Sprites used as `£name£` icons in localised text are named `GFX_text_<name>`
+pub const TEXT_ICON_PREFIX: &str = "GFX_text_";
+
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct SpriteType {
-    name: String,
-    texture_file: String,
+    pub name: String,
+    pub texture_file: String,
 }
 
-pub fn parse_sprites() -> HashMap<String, SpriteType> {
-    let sprites = HashMap::new();
+pub fn parse_sprites(paths: &GamePaths) -> HashMap<String, SpriteType> {
+    let mut sprites = HashMap::new();
 
-    let paths = read_all_files_in_path("./anbennar/interface".to_string());
-    for path in paths {
+    for path in paths.files_in_recursive("interface") {
         if path.extension().unwrap_or("".as_ref()) == "gfx" {
-            let data = fs::read(path).expect("error reading file");
-            let tape = TextTape::from_slice(data.as_slice()).unwrap();
+            let data = fs::read(&path).expect("error reading file");
+            let Ok(tape) = TextTape::from_slice(data.as_slice()) else {
+                println!("Could not parse sprites in {}", path.display());
+                continue;
+            };
             let reader = tape.windows1252_reader();
             for (key, _op, value) in reader.fields() {
                 if key.read_str() == "spriteTypes" {
                     if let Ok(value) = value.read_object() {
                         for (key, _op, value) in value.fields() {
-                            todo!()
+                            if !matches!(key.read_str().as_ref(), "spriteType" | "textSpriteType") {
+                                continue;
+                            }
+                            let Ok(sprite) = value.read_object() else { continue };
+                            let mut parsed = SpriteType::default();
+                            for (key, _op, value) in sprite.fields() {
+                                match key.read_str().to_lowercase().as_str() {
+                                    "name" => parsed.name = value.read_string().unwrap_or_default(),
+                                    "texturefile" => parsed.texture_file = value.read_string().unwrap_or_default(),
+                                    _ => {}
+                                }
+                            }
+                            if !parsed.name.is_empty() {
+                                sprites.insert(parsed.name.clone(), parsed);
+                            }
                         }
                     }
                 }
@@ -35,12 +55,32 @@ pub fn parse_sprites() -> HashMap<String, SpriteType> {
     sprites
 }
 
+/// Icon token (`adm` for `£adm£`) to the name of its file on the wiki, e.g. `icon_adm.png`.
+/// Textures are uploaded as PNGs named after the texture file.
+pub fn text_icons(sprites: &HashMap<String, SpriteType>) -> BTreeMap<String, String> {
+    sprites.values()
+        .filter_map(|sprite| {
+            let token = sprite.name.strip_prefix(TEXT_ICON_PREFIX)?;
+            let stem = Path::new(&sprite.texture_file.replace('\\', "/")).file_stem()?.to_string_lossy().to_string();
+            Some((token.to_string(), format!("{stem}.png")))
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::paths::fixture_paths;
+
     use super::*;
 
     #[test]
     pub fn test_sprites() {
-        // parse_sprites();
+        let sprites = parse_sprites(&fixture_paths());
+        assert_eq!(sprites.get("GFX_text_adm"), Some(&SpriteType { name: "GFX_text_adm".to_string(), texture_file: "gfx/interface/icon_adm.dds".to_string() }));
+        let icons = text_icons(&sprites);
+        assert_eq!(icons.get("adm").map(|file| file.as_str()), Some("icon_adm.png"));
+        // from the mod
+        assert_eq!(icons.get("magic").map(|file| file.as_str()), Some("icon_magic.png"));
+        assert!(!icons.contains_key("topbar"));
     }
-}
\ No newline at end of file
+}
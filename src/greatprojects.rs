@@ -6,6 +6,7 @@ use jomini::text::ObjectReader;
 use serde::Deserialize;
 
 use crate::graphics::SpriteType;
+use crate::paths::GamePaths;
 
 #[derive(Debug, Default)]
 pub struct GreatProject {
@@ -133,18 +134,12 @@ fn extract_modifiers(value: ObjectReader<Windows1252Encoding>) -> BTreeMap<Strin
     map
 }
 
-pub fn parse_all_great_projects() -> Vec<GreatProject> {
+pub fn parse_all_great_projects(paths: &GamePaths) -> Vec<GreatProject> {
     let mut gps = vec![];
-    let paths = fs::read_dir("./anbennar/common/great_projects").expect("Missing great projects directory");
-    for path in paths {
-        match path {
-            Ok(file) => {
-                let data = fs::read(file.path()).expect("error reading file");
-                let parsed = parse_great_projects(data.as_slice());
-                gps.extend(parsed);
-            }
-            _ => {}
-        }
+    for file in paths.files_in("common/great_projects") {
+        let data = fs::read(file).expect("error reading file");
+        let parsed = parse_great_projects(data.as_slice());
+        gps.extend(parsed);
     }
 
     gps
@@ -156,7 +151,7 @@ mod tests {
 
     #[test]
     pub fn test_great_projects() {
-        let projects = parse_all_great_projects();
+        let projects = parse_all_great_projects(&GamePaths::default());
         for p in projects {
             // println!("{:?}", p);
         }
@@ -4,6 +4,11 @@ use std::fs;
 use jomini::{JominiDeserialize, TextTape};
 use serde::Serialize;
 
+use crate::paths::GamePaths;
+
+/// Every national idea set is in this one file
+pub const IDEAS_FILE: &str = "common/ideas/anb_country_ideas.txt";
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct CountryIdeaSets {
     pub idea_sets: BTreeMap<String, IdeaSet>,
@@ -26,9 +31,9 @@ pub struct Idea {
     pub effects: BTreeMap<String, String>,
 }
 
-pub fn parse_ideas() -> CountryIdeaSets {
-    let data = fs::read("./anbennar/common/ideas/anb_country_ideas.txt")
-        .expect("Ideas file not found");
+pub fn parse_ideas(paths: &GamePaths) -> CountryIdeaSets {
+    let file = paths.resolve(IDEAS_FILE).expect("Ideas file not found");
+    let data = fs::read(file).expect("error reading ideas file");
     let mut idea_sets = CountryIdeaSets{
         idea_sets: Default::default()
     };
@@ -110,7 +115,7 @@ mod tests {
 
     #[test]
     pub fn test_idea_parse() {
-        let actual = parse_ideas();
+        let actual = parse_ideas(&GamePaths::default());
         for (_, set) in actual.idea_sets.iter() {
             assert_ne!(set.name, "");
             assert_ne!(set.start.len(), 0);
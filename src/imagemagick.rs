@@ -96,6 +96,8 @@ impl From<&str> for ImageMagick {
 
 #[cfg(test)]
 mod tests {
+    use crate::paths::GamePaths;
+
     use super::*;
 
     #[test]
@@ -105,7 +107,7 @@ mod tests {
     #[test]
     pub fn test_convert_to_png() {
         let magick = ImageMagick::default();
-        let source = Path::new(r"./anbennar/gfx/flags/A03.tga");
-        assert!(!magick.convert_to_png(source).is_none());
+        let source = GamePaths::default().mod_file("gfx/flags/A03.tga");
+        assert!(magick.convert_to_png(&source).is_some());
     }
 }
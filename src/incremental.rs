@@ -0,0 +1,232 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use crate::localisation::parse_localisation_file;
+use crate::map::{parse_province_history, province_file_id};
+use crate::utils::{get_git_changed_files, get_git_commit_hash, get_git_file};
+
+/// Something a generated page is built from
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Input {
+    /// Any file whose path relative to the game root starts with this,
+    /// e.g. `history/countries/A01` or the whole `map/` directory
+    File(String),
+    /// One top level block of the files matching a path prefix,
+    /// e.g. `A01_ideas` in `common/ideas/anb_country_ideas.txt`
+    Block(String, String),
+    /// A localisation key, whichever file defines it
+    Localisation(String),
+    /// The history file of a province, whatever it is named
+    Province(u64),
+    /// Which provinces a tag owns or has a core on
+    Territory(String),
+}
+
+/// The inputs of every page a generator builds, by page title
+#[derive(Debug, Default)]
+pub struct Dependencies {
+    pages: BTreeMap<String, Vec<Input>>,
+}
+
+impl Dependencies {
+    pub fn add(&mut self, title: &str, input: Input) {
+        self.pages.entry(title.to_string()).or_default().push(input);
+    }
+
+    /// Whether the page needs to be generated again. Pages without known inputs always are.
+    pub fn is_affected(&self, title: &str, changes: &Changes) -> bool {
+        match self.pages.get(title) {
+            Some(inputs) => inputs.iter().any(|input| changes.affects(input)),
+            None => true,
+        }
+    }
+}
+
+/// What changed in the mod between two commits
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// Every file added, removed or modified, relative to the mod root
+    pub files: BTreeSet<String>,
+    /// (file, top level key) of every block that was added, removed or edited
+    pub blocks: BTreeSet<(String, String)>,
+    pub localisation_keys: BTreeSet<String>,
+    /// Ids of the changed history/provinces files
+    pub provinces: BTreeSet<u64>,
+    /// Tags that gained or lost an owned or cored province
+    pub territory_tags: BTreeSet<String>,
+}
+
+impl Changes {
+    /// The changes from `commit` to the mod's HEAD, leaving out anything uncommitted
+    pub fn since(mod_root: &Path, commit: &str) -> Option<Changes> {
+        let commit = get_git_commit_hash(mod_root, commit)?;
+        let directory = mod_root.to_string_lossy().to_string();
+        let files = get_git_changed_files(directory, ".".to_string(), format!("{commit}..HEAD"));
+        let mut changes = Changes::default();
+        for file in files {
+            let old = get_git_file(mod_root, &commit, &file);
+            let new = get_git_file(mod_root, "HEAD", &file);
+            changes.add_file(&file, old.as_deref(), new.as_deref());
+        }
+        Some(changes)
+    }
+
+    /// Records a changed file, given its content before and after (None if it didn't exist)
+    pub fn add_file(&mut self, file: &str, old: Option<&[u8]>, new: Option<&[u8]>) {
+        let text = |data: Option<&[u8]>| String::from_utf8_lossy(data.unwrap_or_default()).to_string();
+        if file.starts_with("localisation/") && file.ends_with(".yml") {
+            let old = parse_localisation_file(&text(old)).into_iter().collect();
+            let new = parse_localisation_file(&text(new)).into_iter().collect();
+            self.localisation_keys.extend(changed_keys(&old, &new));
+        } else if file.ends_with(".txt") {
+            let old = top_level_blocks(&text(old));
+            let new = top_level_blocks(&text(new));
+            self.blocks.extend(changed_keys(&old, &new).into_iter().map(|key| (file.to_string(), key)));
+        }
+        if let Some(name) = file.strip_prefix("history/provinces/").and_then(|name| name.strip_suffix(".txt")) {
+            self.provinces.extend(province_file_id(name));
+            let territory = |data: Option<&[u8]>| {
+                let history = parse_province_history(data.unwrap_or_default());
+                history.owner.into_iter().map(|owner| ("owner", owner))
+                    .chain(history.cores.into_iter().map(|core| ("core", core)))
+                    .collect::<BTreeSet<_>>()
+            };
+            let (old, new) = (territory(old), territory(new));
+            self.territory_tags.extend(old.symmetric_difference(&new).map(|(_, tag)| tag.clone()));
+        }
+        self.files.insert(file.to_string());
+    }
+
+    pub fn affects(&self, input: &Input) -> bool {
+        match input {
+            Input::File(prefix) => self.files.iter().any(|file| file.starts_with(prefix.as_str())),
+            Input::Block(prefix, key) => self.blocks.iter().any(|(file, changed)| changed == key && file.starts_with(prefix.as_str())),
+            Input::Localisation(key) => self.localisation_keys.contains(key),
+            Input::Province(id) => self.provinces.contains(id),
+            Input::Territory(tag) => self.territory_tags.contains(tag),
+        }
+    }
+}
+
+fn changed_keys(old: &BTreeMap<String, String>, new: &BTreeMap<String, String>) -> BTreeSet<String> {
+    old.keys().chain(new.keys())
+        .filter(|key| old.get(*key) != new.get(*key))
+        .cloned()
+        .collect()
+}
+
+/// The text of each top level `key = ...` in a game script file, including any comments up to
+/// the next key. Repeated keys (like `country_event`) are joined into one block.
+pub fn top_level_blocks(text: &str) -> BTreeMap<String, String> {
+    let mut blocks: BTreeMap<String, String> = BTreeMap::new();
+    let mut key = String::new();
+    let mut start = 0;
+    let mut token = String::new();
+    let mut token_start = 0;
+    let mut token_ended = false;
+    let mut depth = 0;
+    let (mut in_string, mut in_comment) = (false, false);
+
+    for (i, c) in text.char_indices() {
+        if in_comment {
+            in_comment = c != '\n';
+            continue;
+        }
+        if in_string {
+            in_string = c != '"';
+            continue;
+        }
+        match c {
+            '#' => in_comment = true,
+            '"' => in_string = true,
+            '{' => depth += 1,
+            '}' => depth = (depth - 1).max(0),
+            '=' if depth == 0 && !token.is_empty() => {
+                blocks.entry(key).or_default().push_str(&text[start..token_start]);
+                key = std::mem::take(&mut token);
+                start = token_start;
+                continue;
+            }
+            c if depth == 0 && c.is_whitespace() => {
+                token_ended = true;
+                continue;
+            }
+            c if depth == 0 => {
+                if token_ended || token.is_empty() {
+                    token.clear();
+                    token_start = i;
+                    token_ended = false;
+                }
+                token.push(c);
+                continue;
+            }
+            _ => {}
+        }
+        token.clear();
+    }
+    blocks.entry(key).or_default().push_str(&text[start..]);
+    blocks.retain(|key, text| !key.is_empty() || !text.trim().is_empty());
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_top_level_blocks() {
+        let text = "# header\nA01_ideas = {\n\tstart = { x = 1 }\n\t# a } in a comment\n}\nA02_ideas={ name = \"a { b\" }\ncountry_event = { id = 1 }\ncountry_event = { id = 2 }\nversion = 3\n";
+        let blocks = top_level_blocks(text);
+        assert_eq!(blocks.keys().collect::<Vec<_>>(), vec!["", "A01_ideas", "A02_ideas", "country_event", "version"]);
+        assert_eq!(blocks["A02_ideas"], "A02_ideas={ name = \"a { b\" }\n");
+        assert_eq!(blocks["country_event"], "country_event = { id = 1 }\ncountry_event = { id = 2 }\n");
+        assert_eq!(blocks["version"], "version = 3\n");
+    }
+
+    #[test]
+    pub fn test_changes_affect_inputs() {
+        let mut changes = Changes::default();
+        changes.add_file(
+            "common/ideas/anb_country_ideas.txt",
+            Some(b"A01_ideas = { start = { x = 1 } }\nA02_ideas = { start = { y = 1 } }\n"),
+            Some(b"A01_ideas = { start = { x = 2 } }\nA02_ideas = { start = { y = 1 } }\nA03_ideas = { }\n"),
+        );
+        changes.add_file(
+            "localisation/anb_countries_l_english.yml",
+            Some("l_english:\n A01:0 \"Lorent\"\n A02:0 \"Deranne\"\n".as_bytes()),
+            Some("l_english:\n A01:0 \"Lorent\"\n A02:0 \"Derannic Kingdom\"\n".as_bytes()),
+        );
+        changes.add_file("history/countries/A01 - Lorent.txt", None, Some(b"capital = 1\n"));
+
+        assert!(changes.affects(&Input::Block("common/ideas/".to_string(), "A01_ideas".to_string())));
+        assert!(changes.affects(&Input::Block("common/ideas/anb_country_ideas.txt".to_string(), "A03_ideas".to_string())));
+        assert!(!changes.affects(&Input::Block("common/ideas/".to_string(), "A02_ideas".to_string())));
+        assert!(!changes.affects(&Input::Block("missions/".to_string(), "A01_ideas".to_string())));
+        assert!(changes.affects(&Input::Localisation("A02".to_string())));
+        assert!(!changes.affects(&Input::Localisation("A01".to_string())));
+        assert!(changes.affects(&Input::File("history/countries/A01".to_string())));
+        assert!(!changes.affects(&Input::File("history/countries/A02".to_string())));
+
+        let mut dependencies = Dependencies::default();
+        dependencies.add("Lorent", Input::File("history/countries/A01".to_string()));
+        dependencies.add("Deranne", Input::File("history/countries/A02".to_string()));
+        assert!(dependencies.is_affected("Lorent", &changes));
+        assert!(!dependencies.is_affected("Deranne", &changes));
+        assert!(dependencies.is_affected("Countries", &changes));
+    }
+
+    #[test]
+    pub fn test_changes_affect_territories() {
+        let mut changes = Changes::default();
+        changes.add_file("history/provinces/3 - Deranne.txt", Some(b"owner = A02\nadd_core = A02\n"), Some(b"owner = A01\nadd_core = A02\n"));
+        changes.add_file("history/provinces/12-Anbenncost.txt", Some(b"owner = A03\nbase_tax = 3\n"), Some(b"owner = A03\nbase_tax = 4\n"));
+
+        assert_eq!(changes.provinces, BTreeSet::from([3, 12]));
+        assert!(changes.affects(&Input::Territory("A01".to_string())));
+        assert!(changes.affects(&Input::Territory("A02".to_string())));
+        // A03 keeps the same provinces, only its province file changed
+        assert!(!changes.affects(&Input::Territory("A03".to_string())));
+        assert!(changes.affects(&Input::Province(12)));
+        assert!(!changes.affects(&Input::Province(1)));
+    }
+}
@@ -1,10 +1,13 @@
-use std::borrow::Borrow;
-use std::collections::HashMap;
-use std::fs;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
 
-use regex::Regex;
+use serde::{Deserialize, Serialize};
 
-use crate::countries::Country;
+use crate::cache::ParseCache;
+use crate::paths::GamePaths;
+
+/// Every language falls back to this one for missing keys
+pub const DEFAULT_LANGUAGE: &str = "english";
 
 #[derive(Default)]
 pub struct Localisations {
@@ -13,178 +16,486 @@ pub struct Localisations {
     pub cultures: HashMap<String, String>,
 }
 
-fn parse_localisation_file(data: &str) -> HashMap<String, String> {
-    let mut localisations = HashMap::new();
-    for mut line in data.lines().skip(1) {
-        line = line.trim();
+/// One `KEY:0 "value"` line
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub key: String,
+    pub version: Option<u32>,
+    /// As written between the quotes, escapes included
+    pub value: String,
+    pub line: usize,
+}
+
+/// A localisation file split into entries, and whatever was wrong with it
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LocalisationFile {
+    /// Relative to the mod or base game root
+    #[serde(default)]
+    pub file: String,
+    /// Whether the file is the mod's rather than the base game's
+    #[serde(default)]
+    pub in_mod: bool,
+    /// From the `l_<language>:` header
+    pub language: Option<String>,
+    pub entries: Vec<Entry>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: usize,
+    pub problem: Problem,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Problem {
+    /// The first line isn't `l_<language>:`
+    MissingHeader,
+    /// Not a comment, a header or `KEY:0 "value"`; the line is skipped
+    Malformed(String),
+    /// The value has no closing quote; the rest of the line is used
+    UnterminatedQuote(String),
+    /// The key was already defined earlier in the same file; the later value wins
+    DuplicateKey { key: String, first_line: usize },
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: ", self.file, self.line)?;
+        match &self.problem {
+            Problem::MissingHeader => write!(f, "missing l_<language>: header"),
+            Problem::Malformed(line) => write!(f, "malformed line {line:?}"),
+            Problem::UnterminatedQuote(key) => write!(f, "no closing quote for {key}"),
+            Problem::DuplicateKey { key, first_line } => write!(f, "{key} is already defined on line {first_line}"),
+        }
+    }
+}
+
+/// Splits a localisation file into entries. `file` is only used to label diagnostics.
+pub fn tokenize_localisation(file: &str, data: &str) -> LocalisationFile {
+    let mut parsed = LocalisationFile::default();
+    let mut first_lines: HashMap<String, usize> = HashMap::new();
+    let diagnose = |line: usize, problem: Problem| Diagnostic { file: file.to_string(), line, problem };
+    let mut seen_header = false;
+
+    for (index, line) in data.strip_prefix('\u{feff}').unwrap_or(data).lines().enumerate() {
+        let number = index + 1;
+        let line = line.trim();
         if line.is_empty() || line.starts_with('#') {
             continue;
         }
-        let rows = line.split_once(':');
-        for pieces in rows.iter() {
-            match pieces {
-                (x, y) if x != &"" => {
-                    if y.len() >= 4 {
-                        // Valid entries should have number, space, double quote, (some string), double quote
-                        // and can end with an inline comment
-                        // TODO: not sure how inline comments containing double-quotes are handled
-
-                        let mut val = y.trim_start_matches(char::is_numeric).trim_start();
-
-                        if !val.ends_with('"') {
-                            let last_quote = val.rfind('"');
-                            if let Some(index) = last_quote {
-                                val = &val[0..index]
-                            }
-                        }
-
-                        val = val.strip_prefix('"').unwrap_or_else(|| { val });
-                        val = val.strip_suffix('"').unwrap_or_else(|| { val });
-
-                        // let quote_count: Vec<_> = val.match_indices("\"").collect();
-                        // if quote_count.len() % 2 != 0 {
-                            // eprintln!("mismatched quote count in {:?}", x)
-                        // }
-
-                        localisations.insert(x.to_string(), val.to_string());
-                    } else {
-                        localisations.insert(x.to_string(), "".to_string());
-                    }
-                },
-                _ => {}
+        if !seen_header {
+            seen_header = true;
+            let header = line.split('#').next().unwrap_or_default().trim();
+            if let Some(language) = header.strip_prefix("l_").and_then(|header| header.strip_suffix(':')) {
+                parsed.language = Some(language.to_string());
+                continue;
             }
+            parsed.diagnostics.push(diagnose(number, Problem::MissingHeader));
         }
-    }
 
-    localisations
+        let Some((key, rest)) = line.split_once(':') else {
+            parsed.diagnostics.push(diagnose(number, Problem::Malformed(line.to_string())));
+            continue;
+        };
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let version = rest[..digits].parse().ok();
+        let Some(quoted) = rest[digits..].trim_start().strip_prefix('"') else {
+            parsed.diagnostics.push(diagnose(number, Problem::Malformed(line.to_string())));
+            continue;
+        };
+        if key.is_empty() || key.contains(char::is_whitespace) || key.contains('"') {
+            parsed.diagnostics.push(diagnose(number, Problem::Malformed(line.to_string())));
+            continue;
+        }
+
+        let value = match closing_quote(quoted) {
+            Some(end) => &quoted[..end],
+            None => {
+                parsed.diagnostics.push(diagnose(number, Problem::UnterminatedQuote(key.to_string())));
+                quoted
+            }
+        };
+        if let Some(first_line) = first_lines.insert(key.to_string(), number) {
+            parsed.diagnostics.push(diagnose(number, Problem::DuplicateKey { key: key.to_string(), first_line }));
+        }
+        parsed.entries.push(Entry { key: key.to_string(), version, value: value.to_string(), line: number });
+    }
+    if !seen_header {
+        parsed.diagnostics.push(diagnose(1, Problem::MissingHeader));
+    }
+    parsed
 }
 
-pub fn parse_country_localisations() -> Vec<Country> {
-    let mut tag_map: HashMap<String, Country> = HashMap::new();
-    let file = fs::read("./anbennar/localisation/anb_countries_l_english.yml")
-        .expect("missing country localisation file");
-    let parsed = parse_localisation_file(std::str::from_utf8(file.as_slice()).unwrap());
-    for localisation in parsed {
-        match localisation {
-            (k, v) if k.ends_with("_ADJ") => {
-                let tag = k.strip_suffix("_ADJ").unwrap_or_else(||{k.as_str()});
-                match tag_map.get_mut(tag) {
-                    Some(_) => {
-                        tag_map.get_mut(tag).unwrap().adjective = v;
-                    },
-                    None => {
-                        let mut country = Country::default();
-                        country.tag = String::from(tag);
-                        country.adjective = v.to_string();
-                        tag_map.insert(country.tag.clone(), country);
-                    }
+/// The first unescaped quote followed only by whitespace or a comment. Quotes inside the value
+/// don't need escaping, so `"a "b" c" # "d"` is `a "b" c`.
+fn closing_quote(quoted: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (index, c) in quoted.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => {
+                let rest = quoted[index + 1..].trim_start();
+                if rest.is_empty() || rest.starts_with('#') {
+                    return Some(index);
                 }
-            },
-            (k, v) if k.len() == 3 => {
-                match tag_map.get_mut(&k) {
-                    Some(_) => {
-                        tag_map.get_mut(&k).unwrap().name = v.to_string();
-                    },
-                    None => {
-                        let mut country = Country::default();
-                        country.tag = String::from(&k);
-                        country.name = v.to_string();
-                        if country.name != "" {
-                            tag_map.insert(country.tag.clone(), country);
-                        }
-                    }
-                }
-            },
-            (_, _) => {}
+            }
+            _ => {}
         }
     }
-    tag_map.into_values().collect()
+    None
 }
 
-pub fn parse_idea_localisations() -> HashMap<String, String> {
-    let file = fs::read("./anbennar/localisation/anb_powers_and_ideas_l_english.yml")
-        .expect("missing powers & ideas localisation file");
-    parse_localisation_file(std::str::from_utf8(file.as_slice()).unwrap())
+/// Key to value for every entry in a file, diagnostics are dropped
+pub fn parse_localisation_file(data: &str) -> HashMap<String, String> {
+    tokenize_localisation("", data).entries.into_iter().map(|entry| (entry.key, entry.value)).collect()
 }
 
-pub fn parse_culture_localisations() -> HashMap<String, String> {
-    let file = fs::read("./anbennar/localisation/anb_cultures_l_english.yml")
-        .expect("missing cultures localisation file");
-    parse_localisation_file(std::str::from_utf8(file.as_slice()).unwrap())
+/// Every file in the localisation directory in load order, with diagnostics labelled by the file's path
+pub fn parse_localisation_files(paths: &GamePaths, cache: &ParseCache) -> Vec<LocalisationFile> {
+    let files = paths.files_in("localisation");
+    let parse = |data: &[u8]| tokenize_localisation("", &String::from_utf8_lossy(data));
+    let mut parsed = cache.parse_files("localisations", &files, parse);
+    for (file, parsed) in files.iter().zip(parsed.iter_mut()) {
+        parsed.in_mod = paths.relative_to_mod(file).is_some();
+        let name = paths.relative_to_mod(file)
+            .or_else(|| paths.relative_to_basegame(file))
+            .unwrap_or_else(|| file.display().to_string());
+        parsed.file = name.clone();
+        for diagnostic in parsed.diagnostics.iter_mut() {
+            diagnostic.file = name.clone();
+        }
+        if parsed.language.is_none() {
+            parsed.language = language_from_filename(&name);
+        }
+    }
+    parsed
 }
 
-pub fn parse_religion_localisations() -> HashMap<String, String> {
-    // This file doesn't include all religions
-    let file = fs::read("./anbennar/localisation/anb_religions_l_english.yml")
-        .expect("missing religions localisation file");
-    parse_localisation_file(std::str::from_utf8(file.as_slice()).unwrap())
+/// `english` for `anb_countries_l_english.yml`
+fn language_from_filename(name: &str) -> Option<String> {
+    let stem = name.strip_suffix(".yml")?;
+    stem.rsplit_once("_l_").map(|(_, language)| language.to_string())
 }
 
-pub fn parse_all_localisations() -> HashMap<String, String> {
-    let mut localisations: HashMap<String, String> = HashMap::new();
-    let paths = fs::read_dir("./anbennar/localisation").expect("Missing localisation directory");
-    for path in paths {
-        match path {
-            Ok(file) => {
-                let file = fs::read(file.path()).expect("error reading file");
-                let parsed = parse_localisation_file(std::str::from_utf8(file.as_slice()).unwrap());
-                localisations.extend(parsed);
-            }
-            _ => {}
+/// Key to value for each language, later files overriding earlier ones.
+/// Files without a header or a language in their name count as English.
+pub fn localisations_by_language(files: &[LocalisationFile]) -> BTreeMap<String, HashMap<String, String>> {
+    let mut languages: BTreeMap<String, HashMap<String, String>> = BTreeMap::new();
+    for file in files {
+        let language = file.language.clone().unwrap_or(DEFAULT_LANGUAGE.to_string());
+        let localisations = languages.entry(language).or_default();
+        for entry in &file.entries {
+            localisations.insert(entry.key.clone(), entry.value.clone());
         }
     }
+    languages
+}
+
+/// Key to value in `language`, with English for keys that aren't translated
+pub fn merge_localisations(files: &[LocalisationFile], language: &str) -> HashMap<String, String> {
+    let mut languages = localisations_by_language(files);
+    let mut localisations = languages.remove(DEFAULT_LANGUAGE).unwrap_or_default();
+    if let Some(translated) = languages.remove(language) {
+        localisations.extend(translated);
+    }
     localisations
 }
 
-pub fn colourise(input: &String) -> String {
-    // TODO: there's probably a way to do this all in one?
-    let white = Regex::new(r"§W(.+?)§!").unwrap();
-    let input = white.replace_all(input, "<span class=\"white\">$1</span>");
+/// Every localisation key in English
+#[cfg(test)]
+pub fn parse_all_localisations(paths: &GamePaths, cache: &ParseCache) -> HashMap<String, String> {
+    merge_localisations(&parse_localisation_files(paths, cache), DEFAULT_LANGUAGE)
+}
+
+/// Values for `[Root.GetName]`-style commands and `$KEY$` references that aren't localisation keys,
+/// e.g. `Root.GetName` or `COUNTRY`. Anything without a value becomes a placeholder like "(country name)".
+#[derive(Clone, Debug, Default)]
+pub struct ScopeContext {
+    pub values: HashMap<String, String>,
+}
+
+impl ScopeContext {
+    /// A country as the root scope of the string
+    pub fn country(name: &str, adjective: &str) -> ScopeContext {
+        let mut values = HashMap::new();
+        for scope in ["Root", "This"] {
+            values.insert(format!("{scope}.GetName"), name.to_string());
+            values.insert(format!("{scope}.GetAdjective"), adjective.to_string());
+        }
+        values.insert("COUNTRY".to_string(), name.to_string());
+        values.insert("COUNTRY_ADJ".to_string(), adjective.to_string());
+        ScopeContext { values }
+    }
+
+    /// Army and fleet names, where `$PROVINCE$` is whichever province the unit is raised in
+    pub fn unit_names() -> ScopeContext {
+        let values = HashMap::from([("PROVINCE".to_string(), placeholder("province"))]);
+        ScopeContext { values }
+    }
+}
+
+/// A localised string with its references expanded
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Resolved {
+    pub text: String,
+    /// Every key expanded along the way, nested ones included
+    pub keys: Vec<String>,
+    pub unresolved: Vec<Unresolved>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Unresolved {
+    /// As written, e.g. `$KEY$` or `[Root.GetName]`
+    pub reference: String,
+    pub reason: Unresolvable,
+}
 
-    let blue = Regex::new(r"§B(.+?)§!").unwrap();
-    let input = blue.replace_all(input.borrow(), "<span class=\"blue\">$1</span>");
+#[derive(Clone, Debug, PartialEq)]
+pub enum Unresolvable {
+    /// No localisation key and no value in the context
+    MissingKey,
+    /// The key refers back to itself, through these keys
+    Cycle(Vec<String>),
+    /// A scope command the context has no value for; it became a placeholder
+    NoValue,
+}
 
-    let green = Regex::new(r"§G(.+?)§!").unwrap();
-    let input = green.replace_all(input.borrow(), "<span class=\"green\">$1</span>");
+impl Display for Unresolved {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.reason {
+            Unresolvable::MissingKey => write!(f, "{}: no such localisation key", self.reference),
+            Unresolvable::Cycle(keys) => write!(f, "{}: refers back to itself through {}", self.reference, keys.join(" -> ")),
+            Unresolvable::NoValue => write!(f, "{}: no value in this context", self.reference),
+        }
+    }
+}
 
-    let red = Regex::new(r"§R(.+?)§!").unwrap();
-    let input = red.replace_all(input.borrow(), "<span class=\"red\">$1</span>");
+/// Expands nested `$KEY$` references and `[Scope.Command]` scripted localisation in `text`
+pub fn resolve_localisation(text: &str, localisations: &HashMap<String, String>, context: &ScopeContext) -> Resolved {
+    let mut resolved = Resolved::default();
+    let mut stack = vec![];
+    resolved.text = expand(text, localisations, context, &mut stack, &mut resolved);
+    resolved
+}
 
-    let black = Regex::new(r"§b(.+?)§!").unwrap();
-    let input = black.replace_all(input.borrow(), "<span class=\"black\">$1</span>");
+fn expand(text: &str, localisations: &HashMap<String, String>, context: &ScopeContext, stack: &mut Vec<String>, resolved: &mut Resolved) -> String {
+    let mut output = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(['$', '[']) {
+        output += &rest[..start];
+        let tail = &rest[start..];
+        let (close, is_valid): (char, fn(&str) -> bool) = match tail.starts_with('$') {
+            true => ('$', is_reference),
+            false => (']', is_command),
+        };
+        match tail[1..].find(close) {
+            Some(end) if is_valid(&tail[1..end + 1]) => {
+                let inner = &tail[1..end + 1];
+                let reference = &tail[..end + 2];
+                output += &match close {
+                    '$' => expand_key(inner, reference, localisations, context, stack, resolved),
+                    _ => run_command(inner, reference, context, &mut resolved.unresolved),
+                };
+                rest = &tail[end + 2..];
+            }
+            _ => {
+                output.push(tail.chars().next().unwrap());
+                rest = &tail[1..];
+            }
+        }
+    }
+    output + rest
+}
 
-    let grey = Regex::new(r"§g(.+?)§!").unwrap();
-    let input = grey.replace_all(input.borrow(), "<span class=\"grey\">$1</span>");
+/// `KEY`, or `VALUE|Y` with a format after the bar
+fn is_reference(inner: &str) -> bool {
+    !inner.is_empty() && !inner.contains(char::is_whitespace)
+}
 
-    let yellow = Regex::new(r"§Y(.+?)§!").unwrap();
-    let input = yellow.replace_all(input.borrow(), "<span class=\"yellow\">$1</span>");
+/// `Root.GetName`, `GetYear` or a `?variable`
+fn is_command(inner: &str) -> bool {
+    inner.chars().all(|c| c.is_ascii_alphanumeric() || "_.?|'".contains(c))
+        && (inner.contains('.') || inner.starts_with("Get") || inner.starts_with('?'))
+}
 
-    let marine = Regex::new(r"§M(.+?)§!").unwrap();
-    let input = marine.replace_all(input.borrow(), "<span class=\"marine\">$1</span>");
+fn expand_key(inner: &str, reference: &str, localisations: &HashMap<String, String>, context: &ScopeContext, stack: &mut Vec<String>, resolved: &mut Resolved) -> String {
+    let key = inner.split('|').next().unwrap_or_default();
+    if let Some(value) = context.values.get(key) {
+        return value.clone();
+    }
+    if stack.iter().any(|seen| seen == key) {
+        let mut keys = stack.clone();
+        keys.push(key.to_string());
+        resolved.unresolved.push(Unresolved { reference: reference.to_string(), reason: Unresolvable::Cycle(keys) });
+        return placeholder(&key.to_lowercase().replace('_', " "));
+    }
+    match localisations.get(key) {
+        Some(value) => {
+            resolved.keys.push(key.to_string());
+            stack.push(key.to_string());
+            let expanded = expand(value, localisations, context, stack, resolved);
+            stack.pop();
+            expanded
+        }
+        None => {
+            resolved.unresolved.push(Unresolved { reference: reference.to_string(), reason: Unresolvable::MissingKey });
+            placeholder(&key.to_lowercase().replace('_', " "))
+        }
+    }
+}
 
-    let teal = Regex::new(r"§T(.+?)§!").unwrap();
-    let input = teal.replace_all(input.borrow(), "<span class=\"teal\">$1</span>");
+fn run_command(inner: &str, reference: &str, context: &ScopeContext, unresolved: &mut Vec<Unresolved>) -> String {
+    if let Some(value) = context.values.get(inner) {
+        return value.clone();
+    }
+    unresolved.push(Unresolved { reference: reference.to_string(), reason: Unresolvable::NoValue });
+    placeholder(&describe_command(inner))
+}
 
-    let orange = Regex::new(r"§O(.+?)§!").unwrap();
-    let input = orange.replace_all(input.borrow(), "<span class=\"orange\">$1</span>");
+fn placeholder(description: &str) -> String {
+    format!("({description})")
+}
 
-    let lime = Regex::new(r"§l(.+?)§!").unwrap();
-    let input = lime.replace_all(input.borrow(), "<span class=\"lime\">$1</span>");
+/// "country name" for `Root.GetName`, "monarch name" for `Root.Monarch.GetName`
+fn describe_command(command: &str) -> String {
+    let command = command.trim_start_matches('?').split('|').next().unwrap_or_default();
+    let mut segments: Vec<&str> = command.split('.').collect();
+    let function = segments.pop().unwrap_or_default();
+    let roots = ["root", "this", "from", "fromfrom", "prev", "prevprev"];
+    let had_root = segments.first().is_some_and(|first| roots.contains(&first.to_lowercase().as_str()));
+    segments.retain(|segment| !roots.contains(&segment.to_lowercase().as_str()));
+
+    let mut words: Vec<String> = segments.iter().map(|segment| split_camel_case(segment)).collect();
+    if words.is_empty() && had_root {
+        words.push("country".to_string());
+    }
+    words.push(split_camel_case(function.strip_prefix("Get").unwrap_or(function)));
+    words.retain(|word| !word.is_empty());
+    words.join(" ")
+}
 
-    let jade = Regex::new(r"§J(.+?)§!").unwrap();
-    let input = jade.replace_all(input.borrow(), "<span class=\"jade\">$1</span>");
+/// "heir name" for `HeirName`
+fn split_camel_case(word: &str) -> String {
+    let mut split = String::new();
+    for (index, c) in word.chars().enumerate() {
+        if c.is_uppercase() && index > 0 {
+            split.push(' ');
+        }
+        split.extend(c.to_lowercase());
+    }
+    split.replace('_', " ")
+}
 
-    let purple = Regex::new(r"§P(.+?)§!").unwrap();
-    let input = purple.replace_all(input.borrow(), "<span class=\"purple\">$1</span>");
+/// How coloured text is written on the wiki
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColourStyle {
+    /// `<span class="yellow">text</span>`, styled by the wiki's CSS
+    #[default]
+    Span,
+    /// `{{Color|yellow|text}}`
+    Template,
+}
 
-    let violet = Regex::new(r"§V(.+?)§!").unwrap();
-    let input = violet.replace_all(input.borrow(), "<span class=\"violet\">$1</span>");
+/// A piece of text split on `§X` colour codes
+#[derive(Clone, Debug, PartialEq)]
+pub enum ColourToken<'a> {
+    Text(&'a str),
+    /// `§X`; the colour lasts until the matching `§!` or the end of the text
+    Start(char),
+    /// `§!`, ending the most recent colour
+    End,
+}
 
-    input.to_string()
+pub fn tokenize_colours(input: &str) -> Vec<ColourToken<'_>> {
+    let mut tokens = vec![];
+    let mut rest = input;
+    while let Some(start) = rest.find('§') {
+        if start > 0 {
+            tokens.push(ColourToken::Text(&rest[..start]));
+        }
+        let after = &rest[start + '§'.len_utf8()..];
+        match after.chars().next() {
+            Some('!') => tokens.push(ColourToken::End),
+            Some(code) => tokens.push(ColourToken::Start(code)),
+            None => {}
+        }
+        rest = after.get(after.chars().next().map_or(0, char::len_utf8)..).unwrap_or_default();
+    }
+    if !rest.is_empty() {
+        tokens.push(ColourToken::Text(rest));
+    }
+    tokens
+}
+
+/// The name of a colour code, as used by the wiki's CSS classes and colour template
+fn colour_name(code: char) -> Option<&'static str> {
+    match code {
+        'W' => Some("white"),
+        'B' => Some("blue"),
+        'G' => Some("green"),
+        'R' => Some("red"),
+        'b' => Some("black"),
+        'g' => Some("grey"),
+        'Y' => Some("yellow"),
+        'M' => Some("marine"),
+        'T' => Some("teal"),
+        'O' => Some("orange"),
+        'l' => Some("lime"),
+        'J' => Some("jade"),
+        'P' => Some("purple"),
+        'V' => Some("violet"),
+        _ => None,
+    }
+}
+
+/// Replaces `§X ... §!` colour codes with nested spans or templates. As in the game, `§!` ends the most
+/// recent colour and returns to the one before it, a colour without `§!` lasts to the end of the text,
+/// and colours carry on across line breaks. Unknown codes and stray `§!` only lose their colour.
+pub fn colourise_as(input: &str, style: ColourStyle) -> String {
+    let wrap = |code: char, text: String| match (colour_name(code), style) {
+        _ if text.is_empty() => text,
+        (None, _) => text,
+        (Some(name), ColourStyle::Span) => format!("<span class=\"{name}\">{text}</span>"),
+        (Some(name), ColourStyle::Template) => format!("{{{{Color|{name}|{text}}}}}"),
+    };
+    // the colours open at this point, each with the text written in it so far
+    let mut open: Vec<(char, String)> = vec![];
+    let mut output = String::new();
+    for token in tokenize_colours(input) {
+        match token {
+            ColourToken::Text(text) => match open.last_mut() {
+                Some((_, content)) => content.push_str(text),
+                None => output.push_str(text),
+            },
+            ColourToken::Start(code) => open.push((code, String::new())),
+            ColourToken::End => {
+                let Some((code, content)) = open.pop() else { continue };
+                let wrapped = wrap(code, content);
+                match open.last_mut() {
+                    Some((_, parent)) => parent.push_str(&wrapped),
+                    None => output.push_str(&wrapped),
+                }
+            }
+        }
+    }
+    while let Some((code, content)) = open.pop() {
+        let wrapped = wrap(code, content);
+        match open.last_mut() {
+            Some((_, parent)) => parent.push_str(&wrapped),
+            None => output.push_str(&wrapped),
+        }
+    }
+    output
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::paths::fixture_paths;
+
     use super::*;
 
     #[test]
@@ -207,38 +518,94 @@ mod tests {
     }
 
     #[test]
-    pub fn test_parse_country_localisations() {
-        let parsed = parse_country_localisations();
-        for country in parsed {
-            assert_ne!(country.tag, "");
-            assert_ne!(country.name, "");
-            assert_ne!(country.adjective, "");
-        }
+    pub fn test_tokenize_localisation() {
+        let data = "\u{feff}l_english: # header comment\n # a comment\n\n KEY_A:0 \"Plain\"\n KEY_B: \"No version\"\n KEY_C:12 \"Say \\\"hi\\\"\" # a \"quoted\" comment\n KEY_D:0 \"\"\n";
+        let parsed = tokenize_localisation("loc.yml", data);
+        assert_eq!(parsed.language.as_deref(), Some("english"));
+        assert_eq!(parsed.diagnostics, vec![]);
+        let entries: Vec<(&str, Option<u32>, &str, usize)> = parsed.entries.iter()
+            .map(|e| (e.key.as_str(), e.version, e.value.as_str(), e.line))
+            .collect();
+        assert_eq!(entries, vec![
+            ("KEY_A", Some(0), "Plain", 4),
+            ("KEY_B", None, "No version", 5),
+            ("KEY_C", Some(12), "Say \\\"hi\\\"", 6),
+            ("KEY_D", Some(0), "", 7),
+        ]);
     }
 
     #[test]
-    pub fn test_parse_idea_localisations() {
-        let parsed = parse_idea_localisations();
-        assert_eq!(parsed.get("A01_romance_and_chivalry").unwrap(), "Romance & Chivalry");
+    pub fn test_localisation_diagnostics() {
+        let data = " KEY_A:0 \"One\"\n just some words\n KEY_B:0 \"Unterminated\n KEY_A:1 \"Two\"\n KEY_C:0 value\n";
+        let parsed = tokenize_localisation("localisation/broken_l_english.yml", data);
+        let problems: Vec<(usize, &Problem)> = parsed.diagnostics.iter().map(|d| (d.line, &d.problem)).collect();
+        assert_eq!(problems, vec![
+            (1, &Problem::MissingHeader),
+            (2, &Problem::Malformed("just some words".to_string())),
+            (3, &Problem::UnterminatedQuote("KEY_B".to_string())),
+            (4, &Problem::DuplicateKey { key: "KEY_A".to_string(), first_line: 1 }),
+            (5, &Problem::Malformed("KEY_C:0 value".to_string())),
+        ]);
+        assert_eq!(parsed.diagnostics[3].to_string(), "localisation/broken_l_english.yml:4: KEY_A is already defined on line 1");
+        assert_eq!(parse_localisation_file(data).get("KEY_A").unwrap(), "Two");
+        assert_eq!(parse_localisation_file(data).get("KEY_B").unwrap(), "Unterminated");
+        assert!(tokenize_localisation("empty.yml", "").diagnostics.contains(&Diagnostic { file: "empty.yml".to_string(), line: 1, problem: Problem::MissingHeader }));
     }
 
     #[test]
-    pub fn test_parse_culture_localisations() {
-        let parsed = parse_culture_localisations();
-        assert_eq!(parsed.get("moon_elf").unwrap(), "Moon Elf");
-        assert_eq!(parsed.get("stalboric").unwrap(), "Stalbóric");
+    pub fn test_localisations_by_language() {
+        let paths = fixture_paths();
+        let files = parse_localisation_files(&paths, &ParseCache::disabled());
+        let languages = localisations_by_language(&files);
+        assert_eq!(languages.keys().collect::<Vec<_>>(), vec!["english", "french"]);
+        assert_eq!(languages["french"].get("A02").unwrap(), "Déranne");
+        assert!(!languages["french"].contains_key("A01_winemakers"));
+
+        let french = merge_localisations(&files, "french");
+        assert_eq!(french.get("A01_ideas").unwrap(), "Idées lorentaises");
+        assert_eq!(french.get("A01_winemakers").unwrap(), "Winemakers");
+        assert_eq!(merge_localisations(&files, "german"), merge_localisations(&files, DEFAULT_LANGUAGE));
+        assert_eq!(language_from_filename("localisation/anb_countries_l_braz_por.yml").as_deref(), Some("braz_por"));
     }
 
     #[test]
-    pub fn test_parse_religion_localisations() {
-        let parsed = parse_religion_localisations();
-        assert_eq!(parsed.get("regent_court").unwrap(), "Regent Court");
-        assert_eq!(parsed.get("suhans_praxis").unwrap(), "Suhan's Praxis");
+    pub fn test_resolve_localisation() {
+        let localisations: HashMap<String, String> = [
+            ("GREETING", "Hail $TITLE$ of $REALM|Y$!"),
+            ("TITLE", "the [Root.Monarch.GetTitle]"),
+            ("REALM", "[Root.GetName]"),
+            ("LOOP_A", "a $LOOP_B$"),
+            ("LOOP_B", "b $LOOP_A$"),
+        ].into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+
+        let resolved = resolve_localisation("$GREETING$", &localisations, &ScopeContext::default());
+        assert_eq!(resolved.text, "Hail the (monarch title) of (country name)!");
+        assert_eq!(resolved.keys, vec!["GREETING", "TITLE", "REALM"]);
+        assert_eq!(resolved.unresolved.len(), 2);
+        assert_eq!(resolved.unresolved[1].to_string(), "[Root.GetName]: no value in this context");
+
+        let resolved = resolve_localisation("$GREETING$", &localisations, &ScopeContext::country("Lorent", "Lorentish"));
+        assert_eq!(resolved.text, "Hail the (monarch title) of Lorent!");
+
+        let resolved = resolve_localisation("$PROVINCE$ Guard", &localisations, &ScopeContext::unit_names());
+        assert_eq!(resolved.text, "(province) Guard");
+        assert!(resolved.unresolved.is_empty());
+
+        let resolved = resolve_localisation("$LOOP_A$ and $NOWHERE$", &localisations, &ScopeContext::default());
+        assert_eq!(resolved.text, "a b (loop a) and (nowhere)");
+        assert_eq!(resolved.unresolved, vec![
+            Unresolved { reference: "$LOOP_A$".to_string(), reason: Unresolvable::Cycle(vec!["LOOP_A".to_string(), "LOOP_B".to_string(), "LOOP_A".to_string()]) },
+            Unresolved { reference: "$NOWHERE$".to_string(), reason: Unresolvable::MissingKey },
+        ]);
+
+        // neither of these is a reference
+        let resolved = resolve_localisation("Costs $5 [or more] and [GetYear]", &localisations, &ScopeContext::default());
+        assert_eq!(resolved.text, "Costs $5 [or more] and (year)");
     }
 
     #[test]
     pub fn test_parse_all_localisations() {
-        let parsed = parse_all_localisations();
+        let parsed = parse_all_localisations(&GamePaths::default(), &ParseCache::disabled());
         assert_eq!(parsed.get("regent_court").unwrap(), "Regent Court");
         assert_eq!(parsed.get("dwarven_pantheon").unwrap(), "Dwarven Pantheon");
     }
@@ -248,7 +615,19 @@ mod tests {
         let data = include_str!("../anbennar/localisation/anb_startup_screen_l_english.yml");
         let parsed = parse_localisation_file(data);
         let start = parsed.get("string_start_lorent").unwrap();
-        assert_eq!(colourise(start), include_str!("../tests/fixtures/string_start_lorent.txt"));
+        assert_eq!(colourise_as(start, ColourStyle::Span), include_str!("../tests/fixtures/string_start_lorent.txt"));
+    }
+
+    #[test]
+    pub fn test_colour_codes() {
+        assert_eq!(colourise_as("§YGawed§! and §RAnbennar§!", ColourStyle::Span), "<span class=\"yellow\">Gawed</span> and <span class=\"red\">Anbennar</span>");
+        // nested, then back to the outer colour
+        assert_eq!(colourise_as("§Ya §Rb§! c§! d", ColourStyle::Span), "<span class=\"yellow\">a <span class=\"red\">b</span> c</span> d");
+        // unterminated colours last to the end, across line breaks
+        assert_eq!(colourise_as("§Oa\\nb §Gc", ColourStyle::Template), "{{Color|orange|a\\nb {{Color|green|c}}}}");
+        // stray ends, unknown codes, empty runs and a trailing §
+        assert_eq!(colourise_as("a§! §Zb§! §Y§!c§", ColourStyle::Span), "a b c");
+        assert_eq!(tokenize_colours("x§Yé§!"), vec![ColourToken::Text("x"), ColourToken::Start('Y'), ColourToken::Text("é"), ColourToken::End]);
     }
 
     #[test]
@@ -0,0 +1,316 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use regex::Regex;
+use serde::Serialize;
+
+use crate::countries::parse_country_tags;
+use crate::database::GameDatabase;
+use crate::localisation::{Diagnostic, LocalisationFile};
+use crate::paths::GamePaths;
+use crate::publish::Page;
+
+pub const REPORT_TITLE: &str = "Localisation report";
+
+/// What a localisation key names
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Countries,
+    Ideas,
+    Provinces,
+    Areas,
+    Regions,
+    Reforms,
+    Events,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ReportedKey {
+    pub category: Category,
+    pub key: String,
+    /// Every file in the report's language defining the key
+    pub files: Vec<String>,
+}
+
+/// Localisation keys the wiki's pages need but the mod lacks, and keys the mod has but nothing uses
+#[derive(Debug, Default, Serialize)]
+pub struct LocalisationReport {
+    pub language: String,
+    /// Needed by a page, defined nowhere
+    pub missing: Vec<ReportedKey>,
+    /// Needed by a page, defined as ""
+    pub empty: Vec<ReportedKey>,
+    /// Defined in more than one file, so which value is shown depends on load order
+    pub duplicated: Vec<ReportedKey>,
+    /// Defined by the mod for a tag, province, area, region, reform or event that doesn't exist
+    pub unused: Vec<ReportedKey>,
+    /// Lines of the language's files that couldn't be read as they were meant
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// The keys each page needs: country names, idea names and descriptions, province, area and region names,
+/// the reforms shown on government pages and event titles
+fn required_keys(db: &GameDatabase, paths: &GamePaths) -> BTreeMap<String, Category> {
+    let mut keys = BTreeMap::new();
+    // every tag, including the ones parse_countries skips for having no name
+    for (tag, _) in parse_country_tags(paths) {
+        keys.insert(format!("{tag}_ADJ"), Category::Countries);
+        keys.insert(tag, Category::Countries);
+    }
+    for set in db.ideas.idea_sets.values() {
+        keys.insert(set.name.clone(), Category::Ideas);
+        for idea in &set.ideas {
+            keys.insert(idea.name.clone(), Category::Ideas);
+            keys.insert(format!("{}_desc", idea.name), Category::Ideas);
+        }
+    }
+    for superregion in &db.map {
+        keys.insert(superregion.id.clone(), Category::Regions);
+        for region in &superregion.regions {
+            keys.insert(format!("{}_name", region.id), Category::Regions);
+            for area in &region.areas {
+                keys.insert(format!("{}_name", area.id), Category::Areas);
+                for province in &area.provinces {
+                    keys.insert(format!("PROV{}", province.id), Category::Provinces);
+                }
+            }
+        }
+    }
+    for government in &db.governments {
+        for level in government.reform_levels.values() {
+            keys.insert(level.id.clone(), Category::Reforms);
+            for reform in &level.reforms {
+                keys.insert(reform.clone(), Category::Reforms);
+                keys.insert(format!("{reform}_desc"), Category::Reforms);
+            }
+        }
+    }
+    for event in db.events.iter().flat_map(|set| set.events.iter()) {
+        if !event.title.is_empty() {
+            keys.insert(event.title.clone(), Category::Events);
+        }
+    }
+    keys
+}
+
+/// Recognises keys that name something by their shape, e.g. `PROV123` or `lorent.1.t`
+struct KeyPatterns {
+    tag: Regex,
+    idea_set: Regex,
+    province: Regex,
+    event: Regex,
+}
+
+impl KeyPatterns {
+    fn new() -> KeyPatterns {
+        KeyPatterns {
+            tag: Regex::new(r"^[A-Z][A-Z0-9]{2}(_ADJ)?$").unwrap(),
+            idea_set: Regex::new(r"^[A-Z][A-Z0-9]{2}_ideas$").unwrap(),
+            province: Regex::new(r"^PROV\d+$").unwrap(),
+            event: Regex::new(r"^(.+\.\d+)\.[a-z]+$").unwrap(),
+        }
+    }
+
+    fn category(&self, key: &str) -> Option<Category> {
+        match key {
+            _ if self.tag.is_match(key) => Some(Category::Countries),
+            _ if self.idea_set.is_match(key) => Some(Category::Ideas),
+            _ if self.province.is_match(key) => Some(Category::Provinces),
+            _ if key.ends_with("_area_name") => Some(Category::Areas),
+            _ if key.ends_with("_region_name") || key.ends_with("_superregion") => Some(Category::Regions),
+            _ if key.ends_with("_reform") || key.ends_with("_reform_desc") => Some(Category::Reforms),
+            _ if self.event.is_match(key) => Some(Category::Events),
+            _ => None,
+        }
+    }
+}
+
+/// Checks the localisation files in the database's language against what the pages need
+pub fn check(db: &GameDatabase, paths: &GamePaths, files: &[LocalisationFile]) -> LocalisationReport {
+    let required = required_keys(db, paths);
+    let patterns = KeyPatterns::new();
+    let diagnostics = files.iter()
+        // a file without a header could be in any language
+        .filter(|file| file.language.is_none() || file.language.as_deref() == Some(db.language.as_str()))
+        .flat_map(|file| file.diagnostics.iter().cloned())
+        .collect();
+    let files: Vec<&LocalisationFile> = files.iter()
+        .filter(|file| file.language.as_deref() == Some(db.language.as_str()))
+        .collect();
+    let mut sources: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    // the file loaded last wins, as in the game
+    let mut values: BTreeMap<&str, &str> = BTreeMap::new();
+    for file in &files {
+        for entry in &file.entries {
+            sources.entry(entry.key.as_str()).or_default().insert(file.file.as_str());
+            values.insert(entry.key.as_str(), entry.value.as_str());
+        }
+    }
+    let reported = |category: Category, key: &str| ReportedKey {
+        category,
+        key: key.to_string(),
+        files: sources.get(key).map(|files| files.iter().map(|file| file.to_string()).collect()).unwrap_or_default(),
+    };
+
+    let mut report = LocalisationReport { language: db.language.clone(), diagnostics, ..Default::default() };
+    // only the language's own files count, not the English the pages fall back to
+    for (key, category) in &required {
+        match values.get(key.as_str()) {
+            None => report.missing.push(reported(*category, key)),
+            Some(value) if value.trim().is_empty() => report.empty.push(reported(*category, key)),
+            Some(_) => {}
+        }
+    }
+
+    let events: HashSet<&str> = db.events.iter().flat_map(|set| set.events.iter()).map(|event| event.id.as_str()).collect();
+    let mod_keys: BTreeSet<&str> = files.iter()
+        .filter(|file| file.in_mod)
+        .flat_map(|file| file.entries.iter().map(|entry| entry.key.as_str()))
+        .collect();
+    for key in mod_keys {
+        if required.contains_key(key) {
+            continue;
+        }
+        let Some(category) = patterns.category(key) else { continue };
+        let used = match category {
+            Category::Reforms => db.reforms.contains_key(key.strip_suffix("_desc").unwrap_or(key)),
+            // descriptions and options of an event that exists
+            Category::Events => patterns.event.captures(key).is_some_and(|captures| events.contains(&captures[1])),
+            // every tag is required, as is every idea set, province, area and region
+            Category::Countries | Category::Ideas | Category::Provinces | Category::Areas | Category::Regions => false,
+        };
+        if !used {
+            report.unused.push(reported(category, key));
+        }
+    }
+
+    for (key, defined_in) in &sources {
+        if defined_in.len() > 1 {
+            if let Some(category) = required.get(*key).copied().or_else(|| patterns.category(key)) {
+                report.duplicated.push(reported(category, key));
+            }
+        }
+    }
+    report
+}
+
+impl LocalisationReport {
+    pub fn summary(&self) -> String {
+        format!(
+            "{} missing, {} empty, {} duplicated and {} unused localisation keys, {} unreadable lines",
+            self.missing.len(), self.empty.len(), self.duplicated.len(), self.unused.len(), self.diagnostics.len()
+        )
+    }
+
+    pub fn page(&self) -> Page {
+        let mut text = format!("Problems with the mod's {} localisation keys: {}.\n", self.language, self.summary());
+        let sections = [
+            ("Missing", "Keys a page needs that no file defines.", &self.missing),
+            ("Empty", "Keys a page needs that are defined without any text.", &self.empty),
+            ("Duplicated", "Keys defined in more than one file; the file loaded last wins.", &self.duplicated),
+            ("Unused", "Keys the mod defines for tags, provinces, areas, regions, reforms and events that don't exist.", &self.unused),
+        ];
+        for (heading, description, keys) in sections {
+            if keys.is_empty() {
+                continue;
+            }
+            text += &format!("\n== {heading} ==\n{description}\n");
+            text += "{| class=\"wikitable sortable\"\n! Category !! Key !! Files\n";
+            for key in keys.iter() {
+                let category = serde_json::to_value(key.category).ok()
+                    .and_then(|value| value.as_str().map(|category| category.to_string()))
+                    .unwrap_or_default();
+                let files: Vec<String> = key.files.iter().map(|file| format!("<code>{file}</code>")).collect();
+                text += &format!("|-\n| {category} || <code><nowiki>{}</nowiki></code> || {}\n", key.key, files.join("<br>"));
+            }
+            text += "|}\n";
+        }
+        if !self.diagnostics.is_empty() {
+            text += "\n== Unreadable lines ==\nLines the game will skip or read differently than intended.\n";
+            for diagnostic in &self.diagnostics {
+                text += &format!("* <code><nowiki>{diagnostic}</nowiki></code>\n");
+            }
+        }
+        text += "\n[[Category:Wiki maintenance]]\n";
+        Page::Full { title: REPORT_TITLE.to_string(), text }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::ParseCache;
+    use crate::localisation::{parse_localisation_files, tokenize_localisation};
+    use crate::paths::fixture_paths;
+
+    use super::*;
+
+    fn keys(reported: &[ReportedKey]) -> Vec<(Category, &str)> {
+        reported.iter().map(|key| (key.category, key.key.as_str())).collect()
+    }
+
+    #[test]
+    pub fn test_fixture_report() {
+        let paths = fixture_paths();
+        let cache = ParseCache::disabled();
+        let db = GameDatabase::load(&paths, &cache);
+        let report = check(&db, &paths, &parse_localisation_files(&paths, &cache));
+
+        // only two of Lorent's ideas have names in the fixture
+        let missing = keys(&report.missing);
+        assert_eq!(missing.len(), 12);
+        assert_eq!(missing[0], (Category::Ideas, "A01_lorentish_court"));
+        assert_eq!(missing[10..], [(Category::Countries, "A03"), (Category::Countries, "A03_ADJ")]);
+        assert_eq!(report.missing[10].files, Vec::<String>::new());
+        assert_eq!(keys(&report.empty), vec![(Category::Events, "lorent.2.t")]);
+        assert_eq!(report.empty[0].files, vec!["localisation/lorent_events_l_english.yml"]);
+        assert_eq!(keys(&report.duplicated), vec![(Category::Countries, "A01_ADJ")]);
+        assert_eq!(report.duplicated[0].files, vec!["localisation/anb_countries_l_english.yml", "localisation/anb_powers_and_ideas_l_english.yml"]);
+        assert_eq!(keys(&report.unused), vec![(Category::Provinces, "PROV999"), (Category::Events, "lorent.3.t")]);
+
+        let Page::Full { title, text } = report.page() else { panic!("expected a full page") };
+        assert_eq!(title, "Localisation report");
+        assert!(text.contains("\n== Missing ==\n"));
+        assert!(text.contains("|-\n| events || <code><nowiki>lorent.2.t</nowiki></code> || <code>localisation/lorent_events_l_english.yml</code>\n"));
+        assert!(!text.contains("Unreadable lines"));
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["unused"][0]["category"], "provinces");
+        assert_eq!(json["language"], "english");
+    }
+
+    #[test]
+    pub fn test_report_lists_unreadable_lines() {
+        let paths = fixture_paths();
+        let cache = ParseCache::disabled();
+        let db = GameDatabase::load(&paths, &cache);
+        let mut files = parse_localisation_files(&paths, &cache);
+        assert!(files.iter().all(|file| file.diagnostics.is_empty()));
+        files.push(tokenize_localisation("localisation/broken_l_english.yml", "l_english:\n KEY_A:0 \"a\"\n KEY_A:0 \"b\"\n"));
+        files.push(tokenize_localisation("localisation/broken_l_french.yml", "l_french:\n KEY_B \"b\"\n"));
+
+        let report = check(&db, &paths, &files);
+        assert_eq!(report.diagnostics.len(), 1);
+        assert!(report.summary().ends_with(", 1 unreadable lines"));
+        let Page::Full { text, .. } = report.page() else { panic!("expected a full page") };
+        assert!(text.contains("\n== Unreadable lines ==\n"));
+        assert!(text.contains("* <code><nowiki>localisation/broken_l_english.yml:3: KEY_A is already defined on line 2</nowiki></code>\n"));
+    }
+
+    #[test]
+    pub fn test_fixture_report_in_french() {
+        let paths = fixture_paths();
+        let cache = ParseCache::disabled();
+        let db = GameDatabase::load_language(&paths, &cache, "french");
+        let report = check(&db, &paths, &parse_localisation_files(&paths, &cache));
+
+        // the pages show these in English, but the French files lack them
+        let missing = keys(&report.missing);
+        assert!(missing.contains(&(Category::Provinces, "PROV1")));
+        assert!(missing.contains(&(Category::Events, "lorent.2.t")));
+        assert!(!missing.contains(&(Category::Countries, "A01")));
+        assert!(!missing.contains(&(Category::Ideas, "A01_ideas")));
+        assert!(report.empty.is_empty());
+        assert!(report.duplicated.is_empty());
+        assert!(report.missing.iter().all(|key| key.files.is_empty()));
+        assert_eq!(report.language, "french");
+    }
+}
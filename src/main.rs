@@ -1,179 +1,302 @@
-use std::{env, fs};
-use std::collections::{BTreeMap, HashMap, HashSet};
-use std::fmt::Debug;
-use std::path::{Path, PathBuf};
+use std::error::Error;
+use std::fs;
+use std::collections::BTreeMap;
+use std::path::Path;
 use std::string::String;
-use std::time::Instant;
 
+use clap::Parser;
 use deunicode::deunicode;
-use jomini::{Scalar, TextTape};
-use reqwest::blocking::Client;
-use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT};
-use serde::{Deserialize, Serialize};
+use jomini::Scalar;
 use serde_json::Value;
 
-use crate::countries::{Country, formable_tags};
-use crate::governments::{parse_government_reforms, parse_governments};
-use crate::ideas::parse_ideas;
+use crate::cache::ParseCache;
+use crate::changelog::Changelog;
+use crate::cli::{Cli, Filters, Generator};
+use crate::config::{Config, ConfigError};
+use crate::countries::Country;
+use crate::database::GameDatabase;
+use crate::date::START_DATE;
+use crate::ideas::IDEAS_FILE;
 use crate::imagemagick::ImageMagick;
-use crate::localisation::{parse_all_localisations, parse_idea_localisations};
-use crate::map::{parse_continents, parse_map};
-use crate::missions::tags_with_missions;
+use crate::localisation::{DEFAULT_LANGUAGE, parse_localisation_files, resolve_localisation, ScopeContext};
+use crate::incremental::{Changes, Dependencies, Input};
+use crate::mediawiki::MediaWikiClient;
 use crate::modifiers::get_modifier;
 use crate::modifiers::ModifierNormal::{Negative, Positive};
-use crate::utils::{get_git_changed_files, htmlify};
+use crate::paths::GamePaths;
+use crate::territory::{DEVELOPMENT_TITLE, Territory};
+use crate::publish::{DirectoryPublisher, FileUpload, Page, publish_pages, Publisher, TitleFilter};
+use crate::wikitext::Renderer;
+use crate::utils::{export_git_revision, get_git_changed_files, get_git_commit, get_git_commit_hash, htmlify};
 
+mod cache;
+mod changelog;
+mod cli;
+mod config;
 mod localisation;
+mod localisationreport;
 mod ideas;
 mod countries;
+mod database;
+mod date;
 mod modifiers;
 mod missions;
 mod events;
 mod imagemagick;
+mod incremental;
 mod governments;
 mod utils;
 mod map;
+mod paths;
 mod greatprojects;
 mod graphics;
+mod mediawiki;
+mod publish;
+mod sections;
+mod territory;
+mod wikitext;
+#[cfg(test)]
+mod mockwiki;
 
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let api_url = env::var("API_URL").unwrap();
-    let bot_name = env::var("BOTNAME").unwrap();
-    let bot_pass = env::var("BOTPASS").unwrap();
-
-    let mut mwclient = MediaWikiClient::new(api_url, bot_name, bot_pass);
-    mwclient.login();
-
-    if args.contains(&String::from("--ideas")) {
-        idea_pages(&mut mwclient);
-    }
-    if args.contains(&String::from("--countries")) {
-        country_list_and_details(&mut mwclient);
-    }
-    if args.contains(&String::from("--flags")) {
-        upload_flags(&mut mwclient);
-    }
-    if args.contains(&String::from("--racial-modifiers")) {
-        racial_modifiers(&mut mwclient);
-    }
-    if args.contains(&String::from("--governments")) {
-        run_governments(&mut mwclient);
-    }
-    if args.contains(&String::from("--gov-reform-icons")) {
-        run_government_icons(&mut mwclient)
-    }
-    if args.contains(&String::from("--map")) {
-        run_map(&mut mwclient)
+    let cli = Cli::parse();
+    if let Err(error) = run(cli) {
+        eprintln!("error: {error}");
+        std::process::exit(1);
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct MediaWikiResponse {
-    batchcomplete: String,
-    query: MediaWikiQuery
-}
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let config = Config::load(cli.config.as_deref())?;
+    let generators = cli.generators()?;
+    let mut filters = cli.filters()?;
+    config.validate_mod_root()?;
+    if generators.contains(&Generator::GovReformIcons) {
+        config.validate_basegame_root()?;
+    }
+    let prior_commit = match generators.contains(&Generator::Flags) {
+        true => Some(cli.prior_commit.clone().or(cli.since.clone()).ok_or(ConfigError::MissingPriorCommit)?),
+        false => None,
+    };
+    let paths = GamePaths::from(&config.paths);
+    let changes = match &cli.since {
+        Some(commit) => {
+            let changes = Changes::since(&paths.mod_root, commit).ok_or_else(|| ConfigError::UnknownCommit(commit.clone()))?;
+            println!("{} files changed since {commit}", changes.files.len());
+            Some(changes)
+        }
+        None => None,
+    };
 
-#[derive(Serialize, Deserialize, Debug)]
-struct MediaWikiQuery {
-    tokens: HashMap<String, String>
-}
+    let mut publisher: Box<dyn Publisher> = match &cli.dry_run {
+        Some(directory) => Box::new(DirectoryPublisher::new(directory.clone())),
+        None => {
+            let api_url = config.api_url()?;
+            let (bot_name, bot_pass) = config.credentials()?;
 
-struct MediaWikiClient {
-    url: String,
-    botname: String,
-    botpass: String,
-    csrf_token: Option<String>,
-    csrf_time: Instant,
-    csrf_counter: u8,
-    httpclient: Client,
-}
+            let mut mwclient = MediaWikiClient::new(api_url.clone(), bot_name, bot_pass);
+            mwclient.login().map_err(|e| format!("could not log in to {api_url}: {e}"))?;
+            Box::new(mwclient)
+        }
+    };
+    if let Some(pattern) = filters.titles.take() {
+        publisher = Box::new(TitleFilter::new(publisher, pattern));
+    }
+    let client = publisher.as_mut();
+    let language = cli.language.clone()
+        .or(config.wiki.language.clone())
+        .unwrap_or(DEFAULT_LANGUAGE.to_string());
 
-impl MediaWikiClient {
-    pub fn new(url: String, botname: String, botpass: String) -> MediaWikiClient {
-        let mut default_headers = HeaderMap::new();
-        default_headers.insert(USER_AGENT, HeaderValue::from_static("anbennar-wiki-bot/1.0"));
-        MediaWikiClient{
-            url,
-            botname,
-            botpass,
-            csrf_token: None,
-            csrf_time: Instant::now(),
-            csrf_counter: 0,
-            httpclient: Client::builder()
-                .cookie_store(true)
-                .default_headers(default_headers)
-                .build().unwrap(),
+    let cache = match cli.no_cache {
+        true => ParseCache::disabled(),
+        false => ParseCache::new(&config.paths.cache_directory),
+    };
+    let db = match generators.iter().any(|generator| generator.needs_database()) {
+        true => {
+            let mut db = GameDatabase::load_at(&paths, &cache, &language, cli.date.unwrap_or(START_DATE));
+            db.renderer.icons.extend(config.icons.clone());
+            db.renderer.colours = config.wiki.colours;
+            Some(db)
+        }
+        false => None,
+    };
+
+    for generator in generators {
+        match generator {
+            Generator::Flags => upload_flags(client, &paths, prior_commit.as_deref().unwrap(), &filters),
+            Generator::GovReformIcons => run_government_icons(client, &paths, changes.as_ref()),
+            Generator::Changelog { from, to, json } => {
+                let changelog = changelog(&paths, &config.paths.cache_directory, cli.no_cache, &language, &from, &to)?;
+                fs::write(&json, serde_json::to_string_pretty(&changelog)?)?;
+                println!("Wrote {}", json.display());
+                publish_pages(client, vec![changelog.page(&Renderer { colours: config.wiki.colours, ..Renderer::load(&paths, &config.icons) })]);
+            }
+            Generator::LocalisationReport { json } => {
+                let db = db.as_ref().expect("game database is loaded for the localisation report");
+                let report = localisationreport::check(db, &paths, &parse_localisation_files(&paths, &cache));
+                fs::write(&json, serde_json::to_string_pretty(&report)?)?;
+                println!("Wrote {}: {}", json.display(), report.summary());
+                publish_pages(client, vec![report.page()]);
+            }
+            generator => {
+                let db = db.as_ref().expect("game database is loaded for page generators");
+                let mut pages = generate_pages(&generator, db, &filters);
+                if let Some(changes) = &changes {
+                    let dependencies = page_dependencies(&generator, db);
+                    let total = pages.len();
+                    pages.retain(|page| dependencies.is_affected(page.title(), changes));
+                    println!("{generator:?}: {} of {total} pages have changed inputs", pages.len());
+                }
+                publish_pages(client, pages);
+            }
         }
     }
+    client.finish();
+    Ok(())
+}
 
-    pub fn login(&self) {
-        let params = [("action", "query"), ("meta", "tokens"), ("type", "login"), ("format", "json")];
-        let response = self.httpclient.get(self.url.as_str()).query(&params).send().unwrap().json::<MediaWikiResponse>().unwrap();
-        let logintoken = response.query.tokens.get("logintoken").unwrap().clone();
-        let params = [("action", "login"), ("lgname", self.botname.as_str()), ("lgpassword", self.botpass.as_str()), ("format", "json"), ("lgtoken", &*logintoken)];
-        self.httpclient.post(self.url.as_str()).form(&params).send().unwrap().text().unwrap();
+/// The pages a generator builds from the database
+fn generate_pages(generator: &Generator, db: &GameDatabase, filters: &Filters) -> Vec<Page> {
+    match generator {
+        Generator::Ideas => idea_pages(db, filters),
+        Generator::Countries => country_pages(db, filters),
+        Generator::RacialModifiers => vec![racial_modifiers_page(db)],
+        Generator::Governments => government_pages(db),
+        Generator::Map => vec![map_page(db)],
+        Generator::Flags | Generator::GovReformIcons | Generator::Changelog { .. } | Generator::LocalisationReport { .. } => vec![],
     }
+}
 
-    pub fn csrf(&mut self) -> String {
-        // It's not clear how long the tokens last
-        if self.csrf_token.is_some() && (self.csrf_time.elapsed().as_secs() < 120 && self.csrf_counter < 128) {
-            return self.csrf_token.as_ref().unwrap().to_string();
-        }
+/// Loads the game data at both commits from exports of the mod repository and compares them
+fn changelog(paths: &GamePaths, cache_directory: &Path, no_cache: bool, language: &str, from: &str, to: &str) -> Result<Changelog, Box<dyn Error>> {
+    let load = |side: &str, revision: &str| -> Result<(String, GameDatabase), Box<dyn Error>> {
+        let commit = get_git_commit_hash(&paths.mod_root, revision)
+            .ok_or_else(|| ConfigError::UnknownCommit(revision.to_string()))?;
+        let export = cache_directory.join("revisions").join(side);
+        export_git_revision(&paths.mod_root, &commit, &export)?;
+        let cache = match no_cache {
+            true => ParseCache::disabled(),
+            false => ParseCache::new(cache_directory.join(side)),
+        };
+        // HEAD means nothing once the page is published
+        let label = match revision.starts_with("HEAD") {
+            true => commit[..8].to_string(),
+            false => revision.to_string(),
+        };
+        Ok((label, GameDatabase::load_language(&GamePaths::new(&export, &paths.basegame_root), &cache, language)))
+    };
+    let (from, old) = load("from", from)?;
+    let (to, new) = load("to", to)?;
+    Ok(changelog::diff(&from, &old, &to, &new))
+}
 
-        if self.csrf_counter >= 128 {
-            self.login();
-            self.csrf_counter = 0;
+/// What each page of a generator is built from, so `--since` can skip pages whose inputs are unchanged
+fn page_dependencies(generator: &Generator, db: &GameDatabase) -> Dependencies {
+    let mut dependencies = Dependencies::default();
+    match generator {
+        Generator::Ideas => {
+            for set in db.ideas.idea_sets.values() {
+                let Some(set_name) = db.localise(&set.name) else { continue };
+                let mut inputs = vec![
+                    Input::Block(IDEAS_FILE.to_string(), set.name.clone()),
+                    Input::Localisation(set.name.clone()),
+                ];
+                for idea in &set.ideas {
+                    inputs.extend(localisation_inputs(db, &idea.name));
+                    inputs.extend(localisation_inputs(db, &format!("{}_desc", idea.name)));
+                }
+                // the redirect from the accented name has the same inputs
+                for title in [set_name.to_string(), deunicode(set_name)] {
+                    for input in &inputs {
+                        dependencies.add(&title, input.clone());
+                    }
+                }
+            }
         }
-
-        let params = [("action", "query"), ("meta", "tokens"), ("format", "json")];
-        let response = self.httpclient.get(self.url.as_str()).query(&params).send().unwrap().json::<MediaWikiResponse>().unwrap();
-        let token = response.query.tokens.get("csrftoken").unwrap().to_string();
-        self.csrf_token = Some(token.clone());
-        self.csrf_time = Instant::now();
-        token
-    }
-
-    pub fn upload(&mut self, filename: String, path: &PathBuf) {
-        let form = reqwest::blocking::multipart::Form::new()
-            .text("action", "upload")
-            .text("filename", filename)
-            .text("format", "json")
-            .text("token", self.csrf())
-            .text("ignorewarnings", "1")
-            .file("file", path).unwrap();
-        let x = self.httpclient.post(self.url.as_str()).multipart(form).send().unwrap();
-        self.csrf_counter += 1;
-        println!("{:?}", x.text())
-    }
-
-    pub fn add_edit_page(&mut self, title: &String, text: String) {
-        if title.trim() == "" {
-            panic!("Not editing page without title")
+        Generator::Countries => {
+            for directory in ["common/country_tags/", "history/countries/", "history/provinces/", "map/", "missions/", "decisions/", "events/", "common/scripted_triggers/"] {
+                dependencies.add("Countries", Input::File(directory.to_string()));
+            }
+            for directory in ["common/country_tags/", "history/provinces/"] {
+                dependencies.add(DEVELOPMENT_TITLE, Input::File(directory.to_string()));
+            }
+            for country in &db.countries {
+                let title = deunicode(&country.name);
+                for key in country_list_keys(db, country) {
+                    dependencies.add("Countries", Input::Localisation(key));
+                }
+                for key in country_page_keys(db, country) {
+                    dependencies.add(&title, Input::Localisation(key));
+                }
+                dependencies.add(&title, Input::File(format!("history/countries/{}", country.tag)));
+                dependencies.add(&title, Input::File(country.definition_file.clone()));
+                dependencies.add(&title, Input::Territory(country.tag.clone()));
+                if let Some(territory) = db.territory(&country.tag) {
+                    for id in territory.owned.iter().chain(&territory.cores) {
+                        dependencies.add(&title, Input::Province(*id));
+                    }
+                }
+                dependencies.add(DEVELOPMENT_TITLE, Input::Localisation(country.tag.clone()));
+                if !country.ideas.name.is_empty() {
+                    dependencies.add(&title, Input::Block(IDEAS_FILE.to_string(), country.ideas.name.clone()));
+                }
+                for tree in db.missions.iter().filter(|tree| tree.potential_tags.contains(&country.tag)) {
+                    dependencies.add(&title, Input::Block("missions/".to_string(), tree.name.clone()));
+                }
+            }
         }
-        println!("Updating {title}");
-        let csrf: String = self.csrf();
-        let title = title.clone();
-        let text = text.clone();
-        let summary = format!("Add/edit {}", title);
-        let form = reqwest::blocking::multipart::Form::new()
-            .text("bot", "1")
-            .text("action", "edit")
-            .text("format", "json")
-            .text("title", title)
-            .text("text", text)
-            .text("summary", summary)
-            .text("token", csrf);
-        let x = self.httpclient.post(self.url.as_str()).multipart(form).send().unwrap();
-        self.csrf_counter += 1;
-        println!("{:?}", x.text())
-    }
-
-    pub fn redirect(&mut self, source_title: &String, target_title: &String) {
-        let text = format!("#REDIRECT [[{}]]", target_title);
-        self.add_edit_page(source_title, text);
+        Generator::RacialModifiers => {
+            dependencies.add("Racial_Modifiers", Input::File("common/event_modifiers/racial_admin_military.txt".to_string()));
+        }
+        Generator::Governments => {
+            for government in &db.governments {
+                let title = title_case(&government.id);
+                dependencies.add(&title, Input::Block("common/governments/".to_string(), government.id.clone()));
+                for level in government.reform_levels.values() {
+                    for input in localisation_inputs(db, &level.id) {
+                        dependencies.add(&title, input);
+                    }
+                    for reform in &level.reforms {
+                        dependencies.add(&title, Input::Block("common/government_reforms/".to_string(), reform.clone()));
+                        for input in localisation_inputs(db, reform).into_iter().chain(localisation_inputs(db, &format!("{reform}_desc"))) {
+                            dependencies.add(&title, input);
+                        }
+                    }
+                }
+            }
+        }
+        Generator::Map => {
+            let title = "Geographical list of provinces";
+            for file in ["map/area.txt", "map/region.txt", "map/superregion.txt", "map/continent.txt"] {
+                dependencies.add(title, Input::File(file.to_string()));
+            }
+            for subcontinent in &db.map {
+                dependencies.add(title, Input::Localisation(subcontinent.id.clone()));
+                for region in &subcontinent.regions {
+                    dependencies.add(title, Input::Localisation(format!("{}_name", region.id)));
+                    for area in &region.areas {
+                        dependencies.add(title, Input::Localisation(format!("{}_name", area.id)));
+                        for province in &area.provinces {
+                            dependencies.add(title, Input::Localisation(format!("PROV{}", province.id)));
+                        }
+                    }
+                }
+            }
+        }
+        Generator::Flags | Generator::GovReformIcons | Generator::Changelog { .. } | Generator::LocalisationReport { .. } => {}
     }
+    dependencies
+}
+
+/// A localisation key and every key its text refers to through `$KEY$`
+fn localisation_inputs(db: &GameDatabase, key: &str) -> Vec<Input> {
+    let resolved = resolve_localisation(&format!("${key}$"), &db.localisations, &ScopeContext::default());
+    let mut keys = resolved.keys;
+    keys.push(key.to_string());
+    keys.sort();
+    keys.dedup();
+    keys.into_iter().map(Input::Localisation).collect()
 }
 
 fn title_case(string: &str) -> String {
@@ -184,17 +307,23 @@ fn title_case(string: &str) -> String {
     }
 }
 
-fn idea_pages(client: &mut MediaWikiClient) {
-    let mut country_idea_sets = parse_ideas();
-    let idea_localisations = parse_idea_localisations();
-
-    for (_tag, set) in country_idea_sets.idea_sets.iter_mut() {
-        let set_name = idea_localisations.get(&set.name);
+fn idea_pages(db: &GameDatabase, filters: &Filters) -> Vec<Page> {
+    let mut pages = vec![];
+    for (_tag, set) in db.ideas.idea_sets.iter() {
+        if !set.tags.iter().any(|tag| filters.includes_tag(tag)) {
+            continue;
+        }
+        let set_name = db.localise(&set.name);
         if let Some(set_name) = set_name {
             if set_name == "" {
                 // there was a problem with the localisation
                 continue;
             }
+            // a set for a single country can name it wherever the text refers to the country
+            let context = match set.tags.as_slice() {
+                [tag] => db.country(tag).map(|c| ScopeContext::country(&c.name, &c.adjective)).unwrap_or_default(),
+                _ => ScopeContext::default(),
+            };
             let mut page_body = String::from("{{National Ideas\n");
             page_body += format!("<!-- {set_name} -->\n").as_str();
             let mut counter = 1;
@@ -206,232 +335,399 @@ fn idea_pages(client: &mut MediaWikiClient) {
             }
 
             counter = 1;
-            for idea in set.ideas.iter_mut() {
-                let name = idea_localisations.get(&idea.name);
-                let desc = idea_localisations.get((&format!("{}_desc", &idea.name)).into());
-                if let Some(name) = name {
-                    idea.name = name.to_string();
-                }
-                if let Some(desc) = desc {
-                    idea.description = desc.to_string();
-                }
+            for idea in set.ideas.iter() {
+                let name = db.render(&db.resolve(db.localise(&idea.name).unwrap_or(&idea.name), &context));
+                let desc = db.render(&db.resolve(db.localise(&format!("{}_desc", &idea.name)).unwrap_or(&idea.description), &context));
                 // TODO: Modifier localisations are scattered among many files
 
-                page_body += format!("|idea{counter}name={name}\n", counter=counter, name=idea.name).as_str();
-                page_body += format!("|idea{counter}desc={desc}\n", counter=counter, desc=idea.description).as_str();
+                page_body += format!("|idea{counter}name={name}\n", counter=counter, name=name).as_str();
+                page_body += format!("|idea{counter}desc={desc}\n", counter=counter, desc=desc).as_str();
                 page_body += format!("|idea{counter}effect=").as_str();
-                let mut counter2 = 0;
-                for effect in idea.effects.iter_mut() {
+                for (counter2, effect) in idea.effects.iter().enumerate() {
                     let (desc, value) = modifiers::localise_strings(effect.0, effect.1);
                     if counter2 > 0 {
                         page_body += &*"<br />".to_string();
                     }
                     page_body += format!("{{{{Modifier|type=bonus|value={value}|description={desc} }}}}", value=value, desc=desc).as_str();
-                    counter2 += 1;
                 }
                 page_body += &*"\n".to_string();
                 counter += 1;
             }
 
-            counter = 1;
             page_body += &*"|ambitioneffect=".to_string();
             for effect in set.bonus.iter() {
                 let (desc, value) = modifiers::localise_strings(effect.0, effect.1);
                 page_body += format!("{{{{Modifier|type=bonus|value={value}|description={desc} }}}}", value=value, desc=desc).as_str();
-                counter += 1;
             }
             page_body += "}}\n\n<noinclude>[[Category:Country Ideas]]</noinclude>\n";
             let normal_set_name = deunicode(set_name);
-            if String::ne(set_name, &normal_set_name) {
-                client.redirect(set_name, &normal_set_name);
+            if set_name != normal_set_name {
+                pages.push(Page::Redirect { title: set_name.to_string(), target: normal_set_name.clone() });
             }
-            client.add_edit_page(&normal_set_name, page_body);
+            pages.push(Page::Full { title: normal_set_name, text: page_body });
         }
     }
+    pages
 }
 
-fn upload_flags(client: &mut MediaWikiClient) {
-    let prior_commit = env::var("PRIOR_ANB_COMMIT").unwrap();
-    let changed = get_git_changed_files(
-        String::from("anbennar"),
+fn upload_flags(client: &mut dyn Publisher, paths: &GamePaths, prior_commit: &str, filters: &Filters) {
+    let mod_root = paths.mod_root.to_string_lossy().to_string();
+    let mut changed = get_git_changed_files(
+        mod_root.clone(),
         String::from("gfx/flags"),
         format!("{prior_commit}..HEAD")
     );
+    changed.retain(|flag_path| {
+        let tag = Path::new(flag_path).file_stem().unwrap_or_default();
+        filters.includes_tag(&tag.to_string_lossy())
+    });
+    let commit = get_git_commit(mod_root);
+    let filenames: Vec<String> = changed.iter()
+        .filter_map(|flag_path| Path::new(flag_path).file_stem())
+        .map(|tag| format!("{}_Flag.png", tag.to_str().unwrap()))
+        .collect();
+    client.prefetch_files(&filenames);
+
     for flag_path in changed {
-        let file_path = Path::new("./anbennar").join(&flag_path);
+        let file_path = paths.mod_file(&flag_path);
         let tag = file_path.file_stem().unwrap();
         let file = file_path.as_path();
         if let Some(converted) = ImageMagick::default().convert_to_png(file) {
-            client.upload(format!("{}_Flag.png", tag.to_str().unwrap()), &converted);
+            client.upload(&FileUpload {
+                filename: format!("{}_Flag.png", tag.to_str().unwrap()),
+                path: converted.clone(),
+                source: flag_path.clone(),
+                category: String::from("Flags"),
+                commit: commit.clone(),
+            });
             let _ = fs::remove_file(converted);
         }
     }
 }
 
-fn country_list_and_details(client: &mut MediaWikiClient) {
-    let mut countries = countries::parse_countries();
-    let mission_tags = tags_with_missions();
-    let formable_tags = formable_tags();
-
-    countries.sort_by(|a, b| a.tag.cmp(&b.tag));
-
+fn country_pages(db: &GameDatabase, filters: &Filters) -> Vec<Page> {
+    let mut pages = vec![];
     let mut page_str = String::new();
     page_str += "{| class=\"wikitable sortable\" style=\"text-align: center;\"\n";
     page_str += "|-\n";
-    page_str += "! Flag !! Tag !! Name !! Culture !! Religion !! Continent !! Missions !! Formable !! End-game Tag\n";
-    for country in countries {
+    page_str += "! Flag !! Tag !! Name !! Culture !! Religion !! Capital !! Area !! Region !! Continent !! Provinces !! Development !! Missions !! Formable !! End-game Tag\n";
+    for country in db.countries.iter().filter(|c| filters.includes_tag(&c.tag)) {
         let normal_name = deunicode(country.name.as_str());
         if normal_name == "" {
             continue;
         }
 
+        let capital = db.province(country.history.capital as u64).map(|(province, area, region, _)| (province, area, region));
+        let (provinces, development) = db.territory(&country.tag)
+            .map(|territory| (territory.owned.len(), territory.development()))
+            .unwrap_or_default();
         page_str += "|-\n";
         // TODO: add a template to display a placeholder flag when needed
         page_str += format!(
-            "| [[File:{tag} Flag.png|link={normal_name}|center|64x64px]] || {tag} || [[{normal_name}]] || [[{primary_culture}]] || [[{religion}]] || || {missions} || {formable} || {egt}\n",
+            "| [[File:{tag} Flag.png|link={normal_name}|center|64x64px]] || {tag} || [[{normal_name}]] || [[{primary_culture}]] || [[{religion}]] || {capital} || {area} || {region} || {continent} || {provinces} || {development} || {missions} || {formable} || {egt}\n",
             tag=country.tag,
             normal_name=normal_name,
-            primary_culture=deunicode(country.history.primary_culture.as_str()),
-            religion=deunicode(country.history.religion.as_str()),
-            missions=(||{if mission_tags.contains(&country.tag){"✅"} else {"❌"}})(),
-            formable=(||{if formable_tags.contains(&country.tag){"✅"} else {"❌"}})(),
-            egt=(||{if country.end_game_tag{"✅"} else {"❌"}})()
+            primary_culture=deunicode(db.localise(&country.history.primary_culture).unwrap_or(&country.history.primary_culture)),
+            religion=deunicode(db.localise(&country.history.religion).unwrap_or(&country.history.religion)),
+            capital=capital.map(|(province, _, _)| db.render(&province.name)).unwrap_or_default(),
+            area=capital.map(|(_, area, _)| db.render(&area.name)).unwrap_or_default(),
+            region=capital.map(|(_, _, region)| db.render(&region.name)).unwrap_or_default(),
+            continent=db.continent_name(country.history.capital as u64).unwrap_or(""),
+            provinces=provinces,
+            development=development,
+            missions=if db.has_missions(&country.tag) {"✅"} else {"❌"},
+            formable=if db.is_formable(&country.tag) {"✅"} else {"❌"},
+            egt=if country.end_game_tag {"✅"} else {"❌"}
         ).as_str();
-        country_detail_page(client, country, &mission_tags);
+        pages.push(country_detail_page(db, country));
     }
     page_str += "|}\n";
-    client.add_edit_page(&"Countries".to_string(), page_str);
+    // a partial list would drop every country that was filtered out
+    if !filters.by_tag() {
+        pages.push(Page::Full { title: "Countries".to_string(), text: page_str });
+        pages.push(development_page(db));
+    }
+    pages
 }
 
-fn country_detail_page(client: &mut MediaWikiClient, country: Country, mission_tags: &HashSet<String>) {
-    let ideas = parse_ideas();
-    let idea_localisations = parse_idea_localisations();
+/// Every country owning provinces at the start, largest first
+fn development_page(db: &GameDatabase) -> Page {
+    let mut ranked: Vec<(&Country, &Territory)> = db.countries.iter()
+        .filter_map(|country| Some((country, db.territory(&country.tag)?)))
+        .filter(|(_, territory)| !territory.owned.is_empty())
+        .collect();
+    ranked.sort_by(|(a, a_territory), (b, b_territory)| b_territory.development().cmp(&a_territory.development()).then(a.tag.cmp(&b.tag)));
 
-    let name = deunicode(country.name.as_str());
     let mut page_str = String::new();
-    let mut set_name = "";
-    if let Some(set) = ideas.idea_sets.get(&country.tag) {
-        if let Some(name) = idea_localisations.get(&set.name) {
-            set_name = name;
+    page_str += "{| class=\"wikitable sortable\" style=\"text-align: center;\"\n";
+    page_str += "! Rank !! Country !! Provinces !! Cores !! Development !! Tax !! Production !! Manpower !! Average development\n";
+    for (rank, (country, territory)) in ranked.into_iter().enumerate() {
+        let normal_name = deunicode(&country.name);
+        page_str += &format!(
+            "|-\n| {rank} || [[File:{tag} Flag.png|link={normal_name}|24x24px]] [[{normal_name}]] || {owned} || {cores} || {development} || {tax} || {production} || {manpower} || {average:.1}\n",
+            rank = rank + 1,
+            tag = country.tag,
+            owned = territory.owned.len(),
+            cores = territory.cores.len(),
+            development = territory.development(),
+            tax = territory.base_tax,
+            production = territory.base_production,
+            manpower = territory.base_manpower,
+            average = territory.development() as f64 / territory.owned.len() as f64,
+        );
+    }
+    page_str += "|}\n";
+    Page::Full { title: DEVELOPMENT_TITLE.to_string(), text: page_str }
+}
+
+/// The localisation keys a country's row of the Countries list looks up
+fn country_list_keys(db: &GameDatabase, country: &Country) -> Vec<String> {
+    let mut keys = vec![country.tag.clone(), country.history.primary_culture.clone(), country.history.religion.clone()];
+    // the capital's province, area and region names, as on the map page
+    keys.push(format!("PROV{}", country.history.capital));
+    if let Some((_, area, region, _)) = db.province(country.history.capital as u64) {
+        keys.push(format!("{}_name", area.id));
+        keys.push(format!("{}_name", region.id));
+    }
+    keys
+}
+
+/// The localisation keys a country's page looks up
+fn country_page_keys(db: &GameDatabase, country: &Country) -> Vec<String> {
+    let mut keys = vec![country.tag.clone(), country.history.primary_culture.clone(), country.history.religion.clone()];
+    if !country.ideas.name.is_empty() {
+        keys.push(country.ideas.name.clone());
+    }
+    let history = &country.history;
+    for ruler in [&history.monarch, &history.heir, &history.queen].into_iter().flatten() {
+        if !ruler.culture.is_empty() {
+            keys.push(ruler.culture.clone());
+        }
+        keys.extend(ruler.personalities.iter().cloned());
+    }
+    if let Some(territory) = db.territory(&country.tag) {
+        for counts in [&territory.trade_goods, &territory.cultures, &territory.religions] {
+            keys.extend(counts.keys().cloned());
         }
     }
+    keys.extend(country.definition.historical_idea_groups.iter().cloned());
+    keys
+}
+
+fn country_detail_page(db: &GameDatabase, country: &Country) -> Page {
+    let name = deunicode(country.name.as_str());
+    let mut page_str = String::new();
+    let set_name = db.localise(&country.ideas.name).unwrap_or("");
     page_str += format!(
         "{{{{Country Detail\n|tag={tag}\n|name={name}\n|primary_culture={culture}\n|religion={religion}\n|idea_group={ideas}\n}}}}\n",
         tag=country.tag,
-        name=name,
-        culture=deunicode(country.history.primary_culture.as_str()),
-        religion=deunicode(country.history.religion.as_str()),
-        ideas=deunicode(set_name)
+        name=db.render(&name),
+        culture=deunicode(db.localise(&country.history.primary_culture).unwrap_or(&country.history.primary_culture)),
+        religion=deunicode(db.localise(&country.history.religion).unwrap_or(&country.history.religion)),
+        ideas=db.render(&deunicode(set_name))
     ).as_str();
-    if mission_tags.contains(&country.tag) {
+    page_str += &ruler_section(db, country);
+    page_str += &territory_section(db, country);
+    page_str += &flavour_section(db, country);
+    if db.has_missions(&country.tag) {
         page_str += "\n[[Category:Countries with missions]]\n";
     }
-    client.add_edit_page(&name, page_str);
+    Page::Section { title: name, text: page_str }
 }
 
-fn racial_modifiers(client: &mut MediaWikiClient) {
-    let data = fs::read("./anbennar/common/event_modifiers/racial_admin_military.txt")
-        .expect("Could not find racial modifiers file");
-    let tape = TextTape::from_slice(data.as_slice()).unwrap();
-    let reader = tape.windows1252_reader();
-    let mut page_str = String::new();
+/// The monarch, heir, consort and leaders a country has at the database's date
+fn ruler_section(db: &GameDatabase, country: &Country) -> String {
+    let history = &country.history;
+    let rulers = [("Ruler", &history.monarch), ("Heir", &history.heir), ("Consort", &history.queen)];
+    if rulers.iter().all(|(_, ruler)| ruler.is_none()) && history.leaders.is_empty() {
+        return String::new();
+    }
+    let localised = |key: &str| db.render(db.localise(key).unwrap_or(key));
+    let mut text = format!("\n== Starting ruler ==\nOn {}:\n", db.date);
+    text += "{| class=\"wikitable\"\n! Role !! Name !! Born !! Culture !! Administrative !! Diplomatic !! Military !! Personalities\n";
+    for (role, ruler) in rulers {
+        let Some(ruler) = ruler else { continue };
+        // rulers without a culture of their own have the country's
+        let culture = match ruler.culture.as_str() {
+            "" => &history.primary_culture,
+            culture => culture,
+        };
+        let personalities: Vec<String> = ruler.personalities.iter().map(|personality| localised(personality)).collect();
+        text += &format!(
+            "|-\n| {role} || {name} {dynasty} || {born} || {culture} || {adm} || {dip} || {mil} || {personalities}\n",
+            name = db.render(&ruler.name),
+            dynasty = db.render(&ruler.dynasty),
+            born = ruler.birth_date.map(|date| date.to_string()).unwrap_or_default(),
+            culture = localised(culture),
+            adm = ruler.adm,
+            dip = ruler.dip,
+            mil = ruler.mil,
+            personalities = personalities.join(", "),
+        );
+    }
+    text += "|}\n";
+    if !history.leaders.is_empty() {
+        text += "\n=== Leaders ===\n{| class=\"wikitable sortable\"\n! Type !! Name !! Fire !! Shock !! Manoeuvre !! Siege\n";
+        for leader in &history.leaders {
+            text += &format!(
+                "|-\n| {} || {} || {} || {} || {} || {}\n",
+                title_case(&leader.kind), db.render(&leader.name), leader.fire, leader.shock, leader.manuever, leader.siege
+            );
+        }
+        text += "|}\n";
+    }
+    text
+}
 
-    for (key, _op, value) in reader.fields() {
-        if key.read_str().ends_with("administration") || key.read_str().ends_with("military") {
-            let key = key.read_string();
-            let title: Vec<_> = key.split('_').collect();
-            let title = title.iter().map(|t| title_case(t)).collect::<Vec<_>>().join(" ");
-            if let Ok(modifiers) = value.read_object() {
-                page_str += format!("=== {} ===\n", title).as_str();
-                for (key, _op, value) in modifiers.fields() {
-                    if key.read_str() != "picture" {
-                        if let Some(modifier) = get_modifier(&key.read_string()) {
-                            if let Ok(value) = value.read_scalar() {
-                                let mut colour = "bonus";
-                                let scalar = value.to_f64();
-                                if let Ok(value) = scalar {
-                                    if value.is_sign_positive() && modifier.normal == Negative || value.is_sign_negative() && modifier.normal == Positive {
-                                        colour = "malus";
-                                    }
-                                    let value = modifier.to_human_readable(value as f32);
-                                    page_str += format!("* {{{{subst:Modifier |type={}|value={}|description={} }}}}\n", colour, value, modifier.name).as_str()
-                                } else {
-                                    page_str += format!("* {{{{subst:Modifier |type={}|value={}|description={} }}}}\n", colour, value, modifier.name).as_str()
-                                }
-                            }
-                        }
-                    }
-                }
-                page_str += "\n";
-            }
+/// Provinces, development, trade goods, cultures and religions a country starts with
+fn territory_section(db: &GameDatabase, country: &Country) -> String {
+    let Some(territory) = db.territory(&country.tag) else { return String::new() };
+    let mut text = String::from("\n== Starting territory ==\n");
+    text += "{| class=\"wikitable\"\n! Provinces !! Cores !! Development !! Tax !! Production !! Manpower\n";
+    text += &format!(
+        "|-\n| {} || {} || {} || {} || {} || {}\n|}}\n",
+        territory.owned.len(), territory.cores.len(), territory.development(), territory.base_tax, territory.base_production, territory.base_manpower
+    );
+    for (heading, counts) in [("Trade goods", &territory.trade_goods), ("Cultures", &territory.cultures), ("Religions", &territory.religions)] {
+        if counts.is_empty() {
+            continue;
         }
+        // most common first
+        let mut counts: Vec<(&String, &usize)> = counts.iter().collect();
+        counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        let listed: Vec<String> = counts.iter()
+            .map(|(key, count)| format!("{} ({count})", db.render(db.localise(key).unwrap_or(key))))
+            .collect();
+        text += &format!("; {heading}\n: {}\n", listed.join(", "));
     }
+    text
+}
 
-    client.add_edit_page(&String::from("Racial_Modifiers"), page_str);
+/// Map colour, the idea groups the AI prefers and the names the game picks from, from the tag's common/countries file
+fn flavour_section(db: &GameDatabase, country: &Country) -> String {
+    let definition = &country.definition;
+    let mut text = String::new();
+    if let Some([r, g, b]) = definition.color {
+        let hex = format!("#{r:02x}{g:02x}{b:02x}");
+        text += &format!(
+            "Map colour: <span style=\"display:inline-block;width:1.5em;height:1em;border:1px solid #000;background:{hex}\"></span> <code>{hex}</code>\n"
+        );
+    }
+    if !definition.historical_idea_groups.is_empty() {
+        text += "\n=== Preferred idea groups ===\n";
+        for group in &definition.historical_idea_groups {
+            text += &format!("# {}\n", db.render(db.localise(group).unwrap_or(group)));
+        }
+    }
+    // regnal numbers like "#6" only count earlier rulers
+    let monarch_names = |female: bool| -> Vec<String> {
+        definition.monarch_names.iter()
+            .filter(|(_, weight)| (*weight < 0) == female)
+            .map(|(name, _)| name.split(" #").next().unwrap_or(name).to_string())
+            .collect()
+    };
+    let names = [
+        ("Monarchs", monarch_names(false)),
+        ("Queens", monarch_names(true)),
+        ("Leaders", definition.leader_names.clone()),
+        ("Ships", definition.ship_names.clone()),
+        ("Armies", definition.army_names.clone()),
+        ("Fleets", definition.fleet_names.clone()),
+    ];
+    if names.iter().any(|(_, names)| !names.is_empty()) {
+        text += "\n=== Names ===\n";
+        for (heading, names) in names {
+            if !names.is_empty() {
+                text += &format!("; {heading}\n: {}\n", db.render(&db.resolve(&names.join(", "), &ScopeContext::unit_names())));
+            }
+        }
+    }
+    match text.is_empty() {
+        true => text,
+        false => format!("\n== Flavour ==\n{text}"),
+    }
 }
 
-fn run_government_icons(client: &mut MediaWikiClient) {
-    fn gather(path: String, files: &mut Vec<PathBuf>) -> Vec<PathBuf> {
-        let directory = fs::read_dir(path);
-        if let Ok(directory) = directory {
-            for entry in directory {
-                match entry {
-                    Ok(entry) => {
-                        if entry.path().is_dir() {
-                            gather(entry.path().to_str().unwrap().to_string(), files);
+fn racial_modifiers_page(db: &GameDatabase) -> Page {
+    let mut page_str = String::new();
+
+    for event_modifier in &db.racial_modifiers {
+        if event_modifier.id.ends_with("administration") || event_modifier.id.ends_with("military") {
+            let title: Vec<_> = event_modifier.id.split('_').collect();
+            let title = title.iter().map(|t| title_case(t)).collect::<Vec<_>>().join(" ");
+            page_str += format!("=== {} ===\n", title).as_str();
+            for (key, value) in &event_modifier.modifiers {
+                if key != "picture" {
+                    if let Some(modifier) = get_modifier(key) {
+                        let mut colour = "bonus";
+                        if let Ok(value) = value.parse::<f64>() {
+                            if value.is_sign_positive() && modifier.normal == Negative || value.is_sign_negative() && modifier.normal == Positive {
+                                colour = "malus";
+                            }
+                            let value = modifier.to_human_readable(value as f32);
+                            page_str += format!("* {{{{subst:Modifier |type={}|value={}|description={} }}}}\n", colour, value, modifier.name).as_str()
                         } else {
-                            files.push(entry.path())
+                            page_str += format!("* {{{{subst:Modifier |type={}|value={}|description={} }}}}\n", colour, value, modifier.name).as_str()
                         }
                     }
-                    Err(_) => {}
                 }
             }
+            page_str += "\n";
         }
-        files.to_vec()
     }
 
-    let mut files: Vec<PathBuf> = vec![];
-    let paths = vec![
-        "./anbennar/gfx/interface/government_reform_icons",
-        "./basegame/gfx/interface/government_reform_icons"
-    ];
+    Page::Full { title: "Racial_Modifiers".to_string(), text: page_str }
+}
 
-    for path in paths {
-        gather(path.to_string(), &mut files);
+fn run_government_icons(client: &mut dyn Publisher, paths: &GamePaths, changes: Option<&Changes>) {
+    // icons the mod doesn't override come from the base game
+    let mut files = paths.files_in_recursive("gfx/interface/government_reform_icons");
+    if let Some(changes) = changes {
+        files.retain(|file| paths.relative_to_mod(file).is_some_and(|file| changes.files.contains(&file)));
     }
 
+    let commit = get_git_commit(paths.mod_root.to_string_lossy().to_string());
+    let filenames: Vec<String> = files.iter()
+        .filter_map(|file| file.file_stem())
+        .map(|stem| format!("gov_{}.png", stem.to_str().unwrap()))
+        .collect();
+    client.prefetch_files(&filenames);
+
     for file in files {
         if file.exists() {
             let name = file.as_path().to_str().unwrap().replace("\\", "/");
             if let Some(converted) = ImageMagick::default().convert_to_png(name.as_ref()) {
-                client.upload(format!("gov_{}", converted.file_name().unwrap().to_str().unwrap()), &converted);
+                let (source, commit) = match paths.relative_to_mod(&file) {
+                    Some(source) => (source, commit.clone()),
+                    None => (paths.relative_to_basegame(&file).unwrap_or(name.clone()), None),
+                };
+                client.upload(&FileUpload {
+                    filename: format!("gov_{}", converted.file_name().unwrap().to_str().unwrap()),
+                    path: converted.clone(),
+                    source,
+                    category: String::from("Government reform icons"),
+                    commit,
+                });
                 let _ = fs::remove_file(converted);
             }
         }
     }
 }
 
-fn run_governments(client: &mut MediaWikiClient) {
-    let governments = parse_governments();
-    let localisations = parse_all_localisations();
-    let reforms = parse_government_reforms(Some(&localisations));
-    let reforms = reforms
-        .iter()
-        .map(|v|(v.id.clone(), v))
-        .collect::<HashMap<_, _>>();
-
-    for government in governments {
+fn government_pages(db: &GameDatabase) -> Vec<Page> {
+    let mut pages = vec![];
+    for government in &db.governments {
         match government.id.as_str() {
             "pre_dharma_mapping" => {}
             name => {
                 let mut page_str = String::new();
                 for level in government.reform_levels.keys() {
                     let reform_level = government.reform_levels.get(level).unwrap();
-                    let level_name = localisations.get(&reform_level.id).unwrap();
+                    let level_name = db.render(&db.resolve(db.localise(&reform_level.id).unwrap(), &ScopeContext::default()));
                     page_str += format!("=== Tier {level}: {name} ===\n", level=level, name=level_name).as_str();
                     page_str += "{| class=\"reformtable\" \n|-\n! Icon !! Name !! Potential !! Effects !! Description\n";
                     for reform_id in &reform_level.reforms {
-                        let reform = reforms.get(reform_id).unwrap();
+                        let reform = db.reforms.get(reform_id).unwrap();
                         if reform.name.as_ref().is_none() {
                             continue;
                         }
@@ -444,7 +740,7 @@ fn run_governments(client: &mut MediaWikiClient) {
                                 page_str += format!("|| [[File:gov_{}.png]] ", icon).as_str()
                             }
                         }
-                        page_str += format!("|| {} \n", reform.name.as_ref().unwrap()).as_str();
+                        page_str += format!("|| {} \n", db.render(&db.resolve(reform.name.as_ref().unwrap(), &ScopeContext::default()))).as_str();
                         if reform.potential.is_some() {
                             page_str += "|| ";
                             page_str += htmlify(&serde_json::from_str::<Value>(<Option<String> as Clone>::clone(&reform.potential).expect("could not unwrap").as_str()).unwrap()).as_str();
@@ -468,25 +764,22 @@ fn run_governments(client: &mut MediaWikiClient) {
 
                         match reform.desc.as_ref() {
                             None => {page_str += "|\n"}
-                            Some(mut desc) => {
-                                let desc = desc.replace("\\n", "<br>");
+                            Some(desc) => {
+                                let desc = db.render(&db.resolve(desc, &ScopeContext::default()));
                                 page_str += format!("| {}\n", desc).as_str();
                             }
                         }
                     }
                     page_str += "|}\n";
                 }
-                // println!("{}", page_str);
-                client.add_edit_page(&title_case(name), page_str);
+                pages.push(Page::Section { title: title_case(name), text: page_str });
             }
         }
     }
+    pages
 }
 
-fn run_map(client: &mut MediaWikiClient) {
-    let super_regions = parse_map();
-    let continents = parse_continents();
-
+fn map_page(db: &GameDatabase) -> Page {
     let mut province_list_page = String::new();
     province_list_page.push_str("{| class=\"wikitable sortable\" style=\"font-size:95%; text-align:left\"\n");
     province_list_page.push_str("! ID !! Name !! Continent !! Subcontinent !! Region !! Area\n");
@@ -494,35 +787,21 @@ fn run_map(client: &mut MediaWikiClient) {
 
     let mut rows = BTreeMap::new();
 
-    for subcontinent in super_regions {
-        for region in subcontinent.regions {
-            for area in region.areas {
-                for province in area.provinces {
-                    let continent = match continents.get(&province.id) {
-                        Some(id) => {
-                            match id.as_str() {
-                                "africa" => "Sarhal",
-                                "europe" => "Cannor",
-                                "serpentspine" => "Serpentspine",
-                                "asia" => "Haless",
-                                "north_america" => "North Aelantir",
-                                "south_america" => "South Aelantir",
-                                "oceania" => "Insyaa",
-                                _ => ""
-                            }
-                        }
-                        None => {""}
-                    };
+    for subcontinent in &db.map {
+        for region in &subcontinent.regions {
+            for area in &region.areas {
+                for province in &area.provinces {
+                    let continent = db.continent_name(province.id).unwrap_or("");
                     rows.insert(
                         province.id,
                         format!(
                             "| {id} || {name} || {continent} || {subcontinent} || {region} || {area}\n",
                             id=province.id,
-                            name=province.name.replace("|", "<nowiki>|</nowiki>"),
+                            name=db.render(&province.name),
                             continent=continent,
-                            subcontinent=subcontinent.name,
-                            region=region.name,
-                            area=area.name.replace("|", "<nowiki>|</nowiki>")
+                            subcontinent=db.render(&subcontinent.name),
+                            region=db.render(&region.name),
+                            area=db.render(&area.name)
                         )
                     );
                 }
@@ -530,13 +809,224 @@ fn run_map(client: &mut MediaWikiClient) {
         }
     }
 
-    for (province_id, _continent_id) in continents {
-        if let Some(row) = rows.remove(&province_id) {
+    for province_id in db.continents.keys() {
+        if let Some(row) = rows.remove(province_id) {
             province_list_page.push_str(row.as_str());
             province_list_page.push_str("|-\n");
         }
     }
 
     province_list_page.push_str("|}\n");
-    client.add_edit_page(&"Geographical list of provinces".to_string(), province_list_page);
-}
\ No newline at end of file
+    Page::Full { title: "Geographical list of provinces".to_string(), text: province_list_page }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mockwiki::MockWiki;
+    use crate::paths::fixture_paths;
+
+    use super::*;
+
+    #[test]
+    pub fn test_idea_pages_against_mock_wiki() {
+        let db = GameDatabase::load(&fixture_paths(), &ParseCache::disabled());
+        let wiki = MockWiki::start();
+        let mut client = wiki.client();
+        publish_pages(&mut client, idea_pages(&db, &Filters::default()));
+        let page = wiki.page("Lorentish Ideas").unwrap_or_default();
+        assert!(page.starts_with("{{National Ideas"));
+        assert!(page.contains("|idea1name=Romance & Chivalry\n|idea1desc=Knights of the rose.\n"));
+        assert!(page.contains("|idea2desc=The best wine in <span class=\"yellow\">Cannor</span>, poured by Lorentish nobles.\n"));
+        assert!(page.contains("[[Category:Country Ideas]]"));
+
+        // a second run has nothing to change
+        let edits = wiki.state().edits;
+        let mut client = wiki.client();
+        publish_pages(&mut client, idea_pages(&db, &Filters::default()));
+        assert_eq!(wiki.state().edits, edits);
+        assert_eq!(client.report().created + client.report().changed, 0);
+    }
+
+    #[test]
+    pub fn test_country_list_and_details_against_mock_wiki() {
+        let db = GameDatabase::load(&fixture_paths(), &ParseCache::disabled());
+        let wiki = MockWiki::start();
+        wiki.set_page("Lorent", "Lore written by an editor");
+        let mut client = wiki.client();
+        publish_pages(&mut client, country_pages(&db, &Filters::default()));
+        let countries = wiki.page("Countries").unwrap_or_default();
+        assert!(countries.contains("[[Lorent]]"));
+        assert!(countries.contains("|| [[Regent Court]] || Lorentainé || Lorent || Lencenor || Cannor || 2 || 38 || "));
+        assert!(countries.contains("|| [[Derannic]] || [[Regent Court]] || Deranne || Deranne || Lencenor || Cannor || 1 || 14 || "));
+        // France's capital isn't in the fixture map
+        assert!(countries.contains("|| [[France]] || [[]] || [[]] ||  ||  ||  ||  || 0 || 0 || "));
+        let ranking = wiki.page("Starting nations by development").unwrap_or_default();
+        assert!(ranking.contains("|-\n| 1 || [[File:A01 Flag.png|link=Lorent|24x24px]] [[Lorent]] || 2 || 3 || 38 || 15 || 13 || 10 || 19.0\n|-\n| 2 || "));
+        assert!(!ranking.contains("France"));
+        let lorent = wiki.page("Lorent").unwrap_or_default();
+        assert!(lorent.contains("|tag=A01"));
+        assert!(lorent.contains("|idea_group=Lorentish Ideas"));
+        assert!(lorent.contains("[[Category:Countries with missions]]"));
+        assert!(lorent.contains("\n== Starting ruler ==\nOn 1444.11.11:\n"));
+        assert!(lorent.contains("| Ruler || Kylian VI sil Lorentis || 1391.4.2 || High Lorentish || 3 || 4 || 2 || Just\n"));
+        assert!(lorent.contains("| Heir || Rean sil Lorentis || 1425.8.19 || High Lorentish || 0 || 0 || 0 || Careful\n"));
+        assert!(lorent.contains("| Consort || Ioriel Silmuna || 1180.9.14 || Moon Elf || 5 || 3 || 1 || \n"));
+        assert!(lorent.contains("| General || Ottrac || 2 || 3 || 2 || 1\n"));
+        assert!(!lorent.contains("Adran"));
+        assert!(lorent.contains("\n== Starting territory ==\n{| class=\"wikitable\"\n! Provinces !! Cores !! Development !! Tax !! Production !! Manpower\n|-\n| 2 || 3 || 38 || 15 || 13 || 10\n|}\n"));
+        assert!(lorent.contains("; Trade goods\n: grain (1), wine (1)\n; Cultures\n: High Lorentish (2)\n; Religions\n: Regent Court (2)\n"));
+        assert!(lorent.contains("\n== Flavour ==\nMap colour: <span style=\"display:inline-block;width:1.5em;height:1em;border:1px solid #000;background:#ac1f34\"></span> <code>#ac1f34</code>\n"));
+        assert!(lorent.contains("\n=== Preferred idea groups ===\n# Aristocratic Ideas\n# diplomatic_ideas\n# Economic Ideas\n"));
+        assert!(lorent.contains("; Monarchs\n: Kylian, Rean\n; Queens\n: Ioriel\n; Leaders\n: Ottrac, sil Lorentis\n"));
+        assert!(lorent.contains("; Armies\n: Armée of (province)\n"));
+        // the editor's text from before the bot section existed is kept below it
+        assert!(lorent.ends_with("<!-- END BOT SECTION -->\nLore written by an editor"));
+
+        // prose added around the bot section survives the next run
+        wiki.set_page("Lorent", &format!("{lorent}\n== Strategy ==\nKeep the elves happy."));
+        let mut client = wiki.client();
+        publish_pages(&mut client, country_pages(&db, &Filters::default()));
+        let lorent = wiki.page("Lorent").unwrap_or_default();
+        assert!(lorent.ends_with("Lore written by an editor\n== Strategy ==\nKeep the elves happy."));
+        assert_eq!(lorent.matches("<!-- END BOT SECTION -->").count(), 1);
+    }
+
+    #[test]
+    pub fn test_run_map_against_mock_wiki() {
+        let db = GameDatabase::load(&fixture_paths(), &ParseCache::disabled());
+        let wiki = MockWiki::start();
+        let mut client = wiki.client();
+        publish_pages(&mut client, vec![map_page(&db)]);
+        let provinces = wiki.page("Geographical list of provinces").unwrap_or_default();
+        assert!(provinces.starts_with("{| class=\"wikitable sortable\""));
+        assert!(provinces.contains("| 1 || Lorentainé || Cannor || Western Cannor || Lencenor || Lorent\n"));
+    }
+
+    #[test]
+    pub fn test_generators_are_filtered_by_tag() {
+        let db = GameDatabase::load(&fixture_paths(), &ParseCache::disabled());
+        let filters = Filters { tags: Some(["A02".to_string()].into()), titles: None };
+        let titles: Vec<String> = generate_pages(&Generator::Countries, &db, &filters).iter().map(|page| page.title().to_string()).collect();
+        assert_eq!(titles, vec!["Deranne"]);
+        assert!(generate_pages(&Generator::Ideas, &db, &filters).is_empty());
+    }
+
+    #[test]
+    pub fn test_pages_in_another_language() {
+        let db = GameDatabase::load_language(&fixture_paths(), &ParseCache::disabled(), "french");
+        let pages = generate_pages(&Generator::Ideas, &db, &Filters::default());
+        let titles: Vec<&str> = pages.iter().map(|page| page.title()).collect();
+        // a redirect from the accented name, as on the English wiki
+        assert_eq!(titles, vec!["Idées lorentaises", "Idees lorentaises"]);
+        let Page::Full { text, .. } = &pages[1] else { panic!("expected a full page") };
+        assert!(text.contains("|idea1name=Romance et chevalerie\n|idea1desc=Knights of the rose.\n"));
+        assert!(text.contains("|idea2name=Winemakers\n"));
+    }
+
+    #[test]
+    pub fn test_pages_affected_by_changes() {
+        let db = GameDatabase::load(&fixture_paths(), &ParseCache::disabled());
+        let affected = |generator: Generator, changes: &Changes| -> Vec<String> {
+            let dependencies = page_dependencies(&generator, &db);
+            generate_pages(&generator, &db, &Filters::default()).iter()
+                .map(|page| page.title().to_string())
+                .filter(|title| dependencies.is_affected(title, changes))
+                .collect()
+        };
+
+        let mut changes = Changes::default();
+        changes.add_file("history/countries/A01 - Lorent.txt", Some(b"capital = 1\n"), Some(b"capital = 2\n"));
+        assert_eq!(affected(Generator::Countries, &changes), vec!["Lorent", "Countries"]);
+        assert!(affected(Generator::Ideas, &changes).is_empty());
+        assert!(affected(Generator::Map, &changes).is_empty());
+
+        let mut changes = Changes::default();
+        changes.add_file("common/countries/Lorent.txt", Some(b"color = { 1 2 3 }\n"), Some(b"color = { 3 2 1 }\n"));
+        assert_eq!(affected(Generator::Countries, &changes), vec!["Lorent"]);
+
+        let mut changes = Changes::default();
+        changes.add_file("history/provinces/3 - Deranne.txt", Some(b"owner = A02\n"), Some(b"owner = A01\n"));
+        assert_eq!(affected(Generator::Countries, &changes), vec!["Lorent", "Deranne", "Countries", "Starting nations by development"]);
+
+        // Lorent has a core on Deranne but neither owner changes
+        let mut changes = Changes::default();
+        changes.add_file("history/provinces/3 - Deranne.txt", Some(b"owner = A02\nbase_tax = 3\n"), Some(b"owner = A02\nbase_tax = 4\n"));
+        assert_eq!(affected(Generator::Countries, &changes), vec!["Lorent", "Deranne", "Countries", "Starting nations by development"]);
+
+        let mut changes = Changes::default();
+        changes.add_file(IDEAS_FILE, Some(b"A01_ideas = { }\n"), Some(b"A01_ideas = { start = { } }\n"));
+        assert_eq!(affected(Generator::Ideas, &changes), vec!["Lorentish Ideas"]);
+        assert_eq!(affected(Generator::Countries, &changes), vec!["Lorent"]);
+
+        // a key the description refers to
+        let mut changes = Changes::default();
+        changes.add_file("localisation/anb_powers_and_ideas_l_english.yml", Some(b"l_english:\n CANNOR:0 \"Cannor\"\n"), Some(b"l_english:\n CANNOR:0 \"The West\"\n"));
+        assert_eq!(affected(Generator::Ideas, &changes), vec!["Lorentish Ideas"]);
+
+        let mut changes = Changes::default();
+        changes.add_file("missions/Lorent_Missions.txt", Some(b"lorent_missions_1 = { }\n"), Some(b"lorent_missions_1 = { slot = 1 }\n"));
+        changes.add_file("localisation/anb_map_l_english.yml", Some(b"l_english:\n PROV1:0 \"Lorentaine\"\n"), Some(b"l_english:\n PROV1:0 \"Lorentaine\"\n PROV2:0 \"Rewan\"\n"));
+        assert_eq!(affected(Generator::Countries, &changes), vec!["Lorent", "Countries"]);
+        assert_eq!(affected(Generator::Map, &changes), vec!["Geographical list of provinces"]);
+        assert!(affected(Generator::Governments, &changes).is_empty());
+
+        // a key a reform description refers to, and a culture name
+        let mut changes = Changes::default();
+        changes.add_file("localisation/anb_governments_l_english.yml", Some(b"l_english:\n feudal_king:0 \"king\"\n"), Some(b"l_english:\n feudal_king:0 \"queen\"\n"));
+        changes.add_file("localisation/anb_countries_l_english.yml", Some(b"l_english:\n high_lorentish:0 \"High Lorentish\"\n"), Some(b"l_english:\n high_lorentish:0 \"Lorentish\"\n"));
+        assert_eq!(affected(Generator::Governments, &changes), vec!["Monarchy"]);
+        assert_eq!(affected(Generator::Countries, &changes), vec!["Lorent", "Countries"]);
+
+        // the consort's culture and the heir's personality
+        let mut changes = Changes::default();
+        changes.add_file("localisation/anb_countries_l_english.yml", Some(b"l_english:\n moon_elf:0 \"Moon Elf\"\n"), Some(b"l_english:\n moon_elf:0 \"Moon-elf\"\n"));
+        changes.add_file("localisation/countries_l_english.yml", Some(b"l_english:\n careful_personality:0 \"Careful\"\n"), Some(b"l_english:\n careful_personality:0 \"Cautious\"\n"));
+        assert_eq!(affected(Generator::Countries, &changes), vec!["Lorent"]);
+
+        // the area Lorent's capital is in
+        let mut changes = Changes::default();
+        changes.add_file("localisation/anb_map_l_english.yml", Some(b"l_english:\n lorent_area_name:0 \"Lorent\"\n"), Some(b"l_english:\n lorent_area_name:0 \"Lorentaine\"\n"));
+        assert_eq!(affected(Generator::Countries, &changes), vec!["Countries"]);
+        assert_eq!(affected(Generator::Map, &changes), vec!["Geographical list of provinces"]);
+
+        // a trade good Lorent produces gets a name
+        let mut changes = Changes::default();
+        changes.add_file("localisation/anb_trade_goods_l_english.yml", None, Some(b"l_english:\n grain:0 \"Grain\"\n"));
+        assert_eq!(affected(Generator::Countries, &changes), vec!["Lorent"]);
+
+        // a preferred idea group
+        let mut changes = Changes::default();
+        changes.add_file("localisation/countries_l_english.yml", Some(b"l_english:\n economic_ideas:0 \"Economic Ideas\"\n"), Some(b"l_english:\n economic_ideas:0 \"Economy Ideas\"\n"));
+        assert_eq!(affected(Generator::Countries, &changes), vec!["Lorent"]);
+    }
+
+    #[test]
+    pub fn test_army_and_fleet_names() {
+        let db = GameDatabase::load(&fixture_paths(), &ParseCache::disabled());
+        let lorent = db.country("A01").unwrap();
+        assert!(flavour_section(&db, lorent).contains("; Armies\n: Armée of (province)\n"));
+        // the province a unit is raised in is known, not a missing key
+        for name in &lorent.definition.army_names {
+            assert!(resolve_localisation(name, &db.localisations, &ScopeContext::unit_names()).unresolved.is_empty());
+        }
+    }
+
+    #[test]
+    pub fn test_government_and_racial_modifier_pages() {
+        let db = GameDatabase::load(&fixture_paths(), &ParseCache::disabled());
+        let pages = government_pages(&db);
+        assert_eq!(pages.len(), 1);
+        let Page::Section { title, text } = &pages[0] else { panic!("expected a section") };
+        assert_eq!(title, "Monarchy");
+        assert!(text.starts_with("=== Tier 1: Monarchy Type ===\n"));
+        assert!(text.contains("|| [[File:gov_feudalism.png]] || Feudal Monarchy \n"));
+        assert!(text.contains("| Lords hold the land.<br>The king holds the lords.\n"));
+        assert!(text.contains("| (monarch name) answers to no one.\n"));
+        assert!(text.contains("| [[File:icon_adm.png|link=]] Power flows to the capital.\n"));
+
+        let Page::Full { text, .. } = racial_modifiers_page(&db) else { panic!("expected a full page") };
+        assert!(text.contains("=== Elven Administration ===\n"));
+        assert!(text.contains("=== Human Military ===\n"));
+        assert!(!text.contains("Elven Feast"));
+    }
+}
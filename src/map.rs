@@ -3,9 +3,11 @@ use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::hash::{Hash, Hasher};
 
-use jomini::{JominiDeserialize, TextTape};
+use jomini::TextTape;
+use serde::{Deserialize, Serialize};
 
-use crate::localisation::parse_all_localisations;
+use crate::cache::ParseCache;
+use crate::paths::GamePaths;
 
 #[derive(Debug, Eq)]
 pub struct Province {
@@ -36,10 +38,9 @@ impl Borrow<u64> for Province {
     }
 }
 
-#[derive(Debug, JominiDeserialize, Eq, PartialEq)]
+#[derive(Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 #[derive(Clone)]
 pub struct ProvinceHistory {
-    #[jomini(take_last)]
     pub owner: Option<String>,
     pub controller: Option<String>,
     pub culture: Option<String>,
@@ -47,9 +48,46 @@ pub struct ProvinceHistory {
     pub base_tax: Option<u64>,
     pub base_production: Option<u64>,
     pub base_manpower: Option<u64>,
-    #[jomini(take_last)]
     pub trade_goods: Option<String>,
-    pub is_city: Option<bool>
+    pub is_city: Option<bool>,
+    /// add_core, less remove_core
+    #[serde(default)]
+    pub cores: Vec<String>,
+}
+
+/// Reads the undated part of a history/provinces file, later entries win
+pub fn parse_province_history(data: &[u8]) -> ProvinceHistory {
+    let mut history = ProvinceHistory::default();
+    let Ok(tape) = TextTape::from_slice(data) else { return history };
+    let reader = tape.windows1252_reader();
+    for (key, _op, value) in reader.fields() {
+        let number = || value.read_scalar().ok().and_then(|v| v.to_u64().ok());
+        match key.read_str().as_ref() {
+            "owner" => history.owner = value.read_string().ok(),
+            "controller" => history.controller = value.read_string().ok(),
+            "culture" => history.culture = value.read_string().ok(),
+            "religion" => history.religion = value.read_string().ok(),
+            "base_tax" => history.base_tax = number(),
+            "base_production" => history.base_production = number(),
+            "base_manpower" => history.base_manpower = number(),
+            "trade_goods" => history.trade_goods = value.read_string().ok(),
+            "is_city" => history.is_city = value.read_scalar().ok().and_then(|v| v.to_bool().ok()),
+            "add_core" => {
+                if let Ok(tag) = value.read_string() {
+                    if !history.cores.contains(&tag) {
+                        history.cores.push(tag);
+                    }
+                }
+            }
+            "remove_core" => {
+                if let Ok(tag) = value.read_string() {
+                    history.cores.retain(|core| *core != tag);
+                }
+            }
+            _ => {}
+        }
+    }
+    history
 }
 
 #[derive(Debug, PartialEq)]
@@ -105,8 +143,22 @@ pub struct SuperRegion {
     pub restrict_charter: bool
 }
 
-pub fn parse_continents() -> BTreeMap<u64, String> {
-    let file = fs::read("./anbennar/map/continent.txt").expect("error reading file");
+/// What the mod calls the base game's continents
+pub fn continent_name(continent: &str) -> Option<&'static str> {
+    match continent {
+        "africa" => Some("Sarhal"),
+        "europe" => Some("Cannor"),
+        "serpentspine" => Some("Serpentspine"),
+        "asia" => Some("Haless"),
+        "north_america" => Some("North Aelantir"),
+        "south_america" => Some("South Aelantir"),
+        "oceania" => Some("Insyaa"),
+        _ => None,
+    }
+}
+
+pub fn parse_continents(paths: &GamePaths) -> BTreeMap<u64, String> {
+    let file = fs::read(paths.resolve("map/continent.txt").expect("missing map/continent.txt")).expect("error reading file");
     let tape = TextTape::from_slice(file.as_slice()).unwrap();
     let reader = tape.windows1252_reader();
     let mut data: BTreeMap<u64, String> = BTreeMap::new();
@@ -123,22 +175,19 @@ pub fn parse_continents() -> BTreeMap<u64, String> {
     data
 }
 
-pub fn parse_continents_inverse() -> HashMap<String, Vec<u64>> {
+pub fn parse_continents_inverse(paths: &GamePaths) -> HashMap<String, Vec<u64>> {
     let mut data =  HashMap::new();
-    for (k, v) in parse_continents() {
+    for (k, v) in parse_continents(paths) {
         data.entry(v).or_insert_with(Vec::new).push(k);
     }
     data
 }
 
-pub fn parse_map() -> Vec<SuperRegion> {
+pub fn parse_map(paths: &GamePaths, histories: &BTreeMap<u64, ProvinceHistory>, localisations: &HashMap<String, String>) -> Vec<SuperRegion> {
     // Continent > Super region (subcontinent) > Region > Area > Province
-    
-    let localisations = parse_all_localisations();
-    let histories = parse_province_histories();
 
     // AREAS & PROVINCES
-    let file = fs::read("./anbennar/map/area.txt").expect("error reading file");
+    let file = fs::read(paths.resolve("map/area.txt").expect("missing map/area.txt")).expect("error reading file");
     let tape = TextTape::from_slice(file.as_slice()).unwrap();
     let reader = tape.windows1252_reader();
     let mut areas = HashSet::new();
@@ -174,7 +223,7 @@ pub fn parse_map() -> Vec<SuperRegion> {
     }
 
     // REGIONS
-    let file = fs::read("./anbennar/map/region.txt").expect("error reading file");
+    let file = fs::read(paths.resolve("map/region.txt").expect("missing map/region.txt")).expect("error reading file");
     let tape = TextTape::from_slice(file.as_slice()).unwrap();
     let reader = tape.windows1252_reader();
     let mut regions = HashSet::new();
@@ -205,7 +254,7 @@ pub fn parse_map() -> Vec<SuperRegion> {
     }
 
     // SUPER REGIONS
-    let file = fs::read("./anbennar/map/superregion.txt").expect("error reading file");
+    let file = fs::read(paths.resolve("map/superregion.txt").expect("missing map/superregion.txt")).expect("error reading file");
     let tape = TextTape::from_slice(file.as_slice()).unwrap();
     let reader = tape.windows1252_reader();
     let mut super_regions = vec![];
@@ -238,47 +287,55 @@ pub fn parse_map() -> Vec<SuperRegion> {
     super_regions
 }
 
-pub fn parse_province_histories() -> BTreeMap<u64, ProvinceHistory> {
+pub fn parse_province_histories(paths: &GamePaths, cache: &ParseCache) -> BTreeMap<u64, ProvinceHistory> {
     let mut histories = BTreeMap::new();
-    let paths = fs::read_dir("./anbennar/history/provinces")
-        .expect("Missing province history directory");
-    for path in paths {
-        match path {
-            Ok(file) => {
-                let file_name = file.path();
-                let mut name = file_name.file_stem().unwrap().to_str().unwrap();
-                if name.contains('-') {
-                    name = name.split('-').next().unwrap().trim();
-                }
-                let id = name.parse::<u64>().unwrap();
-                let file = fs::read(file.path()).expect("error reading file");
-                let history = jomini::TextDeserializer::from_windows1252_slice(file.as_slice());
-                let history: ProvinceHistory = history.unwrap().deserialize().unwrap();
-                histories.insert(id, history);
-            }
-            _ => {}
-        }
-        // break;
+    let files = paths.files_in("history/provinces");
+    let parsed = cache.parse_files("province_histories", &files, parse_province_history);
+    for (file_name, history) in files.iter().zip(parsed) {
+        let id = province_file_id(file_name.file_stem().unwrap().to_str().unwrap()).unwrap();
+        histories.insert(id, history);
     }
     histories
 }
 
+/// The province id of a history/provinces file name, e.g. 1 for `1 - Lorentaine`
+pub fn province_file_id(file_stem: &str) -> Option<u64> {
+    file_stem.split('-').next()?.trim().parse().ok()
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::localisation::parse_all_localisations;
+
     use super::*;
 
     #[test]
     fn test_parse_map() {
-        let map = parse_map();
+        let paths = GamePaths::default();
+        let cache = ParseCache::disabled();
+        let map = parse_map(&paths, &parse_province_histories(&paths, &cache), &parse_all_localisations(&paths, &cache));
+        assert!(!map.is_empty());
+    }
+
+    #[test]
+    fn test_parse_province_history() {
+        let history = parse_province_history(b"owner = A01\nbase_tax = 3\nis_city = yes\ntrade_goods = grain\nadd_core = A01\nadd_core = A02\nadd_core = A03\n\
+            1500.1.1 = { owner = A02 }\ntrade_goods = wine\nremove_core = A02\nadd_core = A01\n");
+        assert_eq!(history.owner.as_deref(), Some("A01"));
+        assert_eq!(history.base_tax, Some(3));
+        assert_eq!(history.is_city, Some(true));
+        assert_eq!(history.trade_goods.as_deref(), Some("wine"));
+        assert_eq!(history.culture, None);
+        assert_eq!(history.cores, vec!["A01", "A03"]);
     }
 
     #[test]
     fn test_parse_continents() {
-        parse_continents();
+        parse_continents(&GamePaths::default());
     }
 
     #[test]
     fn test_parse_continents_inverse() {
-        parse_continents_inverse();
+        parse_continents_inverse(&GamePaths::default());
     }
 }
\ No newline at end of file
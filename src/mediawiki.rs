@@ -0,0 +1,756 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER, USER_AGENT};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::publish::{EditOutcome, FileUpload, PublishReport, Publisher};
+use crate::utils::sha1_file;
+
+/// The API refuses to return content for more than 50 titles per request
+const REVISION_BATCH_SIZE: usize = 50;
+/// How many times a request is attempted before its error is reported
+const MAX_ATTEMPTS: u32 = 5;
+/// Ask the API to refuse writes while replication lag is above this many seconds
+const MAXLAG: &str = "5";
+
+#[derive(Debug)]
+pub enum MediaWikiError {
+    Http(reqwest::Error),
+    BadToken,
+    /// The session expired or the bot was logged out (`assertbotfailed`, `assertuserfailed`, `notloggedin`)
+    SessionLost,
+    RateLimited { retry_after: Option<Duration> },
+    MaxLag { retry_after: Option<Duration> },
+    PermissionDenied { code: String, info: String },
+    LoginFailed(String),
+    Api { code: String, info: String },
+    UnexpectedResponse(String),
+}
+
+impl MediaWikiError {
+    /// Maps the `error` object of an API response to a typed error
+    fn from_api(error: &Value, retry_after: Option<Duration>) -> MediaWikiError {
+        let code = error.get("code").and_then(Value::as_str).unwrap_or_default().to_string();
+        let info = error.get("info").and_then(Value::as_str).unwrap_or_default().to_string();
+        match code.as_str() {
+            "badtoken" => MediaWikiError::BadToken,
+            "assertbotfailed" | "assertuserfailed" | "assertnameduserfailed" | "notloggedin" => MediaWikiError::SessionLost,
+            "ratelimited" => MediaWikiError::RateLimited { retry_after },
+            "maxlag" => MediaWikiError::MaxLag { retry_after },
+            "permissiondenied" | "protectedpage" | "cascadeprotected" | "protectedtitle" | "blocked"
+            | "writeapidenied" | "readapidenied" | "mwoauth-invalid-authorization" => MediaWikiError::PermissionDenied { code, info },
+            _ => MediaWikiError::Api { code, info },
+        }
+    }
+}
+
+impl Display for MediaWikiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MediaWikiError::Http(e) => write!(f, "HTTP error: {e}"),
+            MediaWikiError::BadToken => write!(f, "invalid CSRF token"),
+            MediaWikiError::SessionLost => write!(f, "session lost"),
+            MediaWikiError::RateLimited { .. } => write!(f, "rate limited"),
+            MediaWikiError::MaxLag { .. } => write!(f, "database lag above maxlag"),
+            MediaWikiError::PermissionDenied { code, info } => write!(f, "permission denied ({code}): {info}"),
+            MediaWikiError::LoginFailed(reason) => write!(f, "login failed: {reason}"),
+            MediaWikiError::Api { code, info } => write!(f, "API error ({code}): {info}"),
+            MediaWikiError::UnexpectedResponse(body) => write!(f, "unexpected response: {body}"),
+        }
+    }
+}
+
+impl std::error::Error for MediaWikiError {}
+
+impl From<reqwest::Error> for MediaWikiError {
+    fn from(e: reqwest::Error) -> Self {
+        MediaWikiError::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for MediaWikiError {
+    fn from(e: serde_json::Error) -> Self {
+        MediaWikiError::UnexpectedResponse(e.to_string())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct MediaWikiResponse {
+    batchcomplete: String,
+    query: MediaWikiQuery
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct MediaWikiQuery {
+    tokens: HashMap<String, String>
+}
+
+#[derive(Deserialize, Debug)]
+struct QueryResponse {
+    #[serde(default)]
+    query: Option<Query>,
+    /// When the server answered, if `curtimestamp` was asked for
+    #[serde(default)]
+    curtimestamp: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Query {
+    #[serde(default)]
+    normalized: Vec<NormalizedTitle>,
+    #[serde(default)]
+    pages: Vec<QueryPage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct NormalizedTitle {
+    from: String,
+    to: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct QueryPage {
+    title: String,
+    #[serde(default)]
+    missing: bool,
+    #[serde(default)]
+    revisions: Vec<Revision>,
+    #[serde(default)]
+    imageinfo: Vec<ImageInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ImageInfo {
+    sha1: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Revision {
+    #[serde(default)]
+    timestamp: Option<String>,
+    slots: HashMap<String, RevisionSlot>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RevisionSlot {
+    #[serde(default)]
+    content: String,
+}
+
+/// A page as it was when it was fetched, so an edit based on it can be refused if someone edited it since
+#[derive(Debug, Clone, PartialEq)]
+struct FetchedRevision {
+    /// `None` if the page does not exist
+    content: Option<String>,
+    /// Timestamp of the revision `content` is from, sent as `basetimestamp`
+    timestamp: Option<String>,
+    /// When the page was fetched, sent as `starttimestamp`
+    fetched_at: Option<String>,
+}
+
+pub struct MediaWikiClient {
+    url: String,
+    botname: String,
+    botpass: String,
+    csrf_token: Option<String>,
+    csrf_time: Instant,
+    csrf_counter: u8,
+    httpclient: Client,
+    // Current content of pages
+    revisions: HashMap<String, FetchedRevision>,
+    // SHA-1 of uploaded files by file name, `None` if the file does not exist yet
+    file_hashes: HashMap<String, Option<String>>,
+    report: PublishReport,
+    /// Initial delay between retries, doubled after every failed attempt
+    pub retry_delay: Duration,
+}
+
+impl MediaWikiClient {
+    pub fn new(url: String, botname: String, botpass: String) -> MediaWikiClient {
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert(USER_AGENT, HeaderValue::from_static("anbennar-wiki-bot/1.0"));
+        MediaWikiClient{
+            url,
+            botname,
+            botpass,
+            csrf_token: None,
+            csrf_time: Instant::now(),
+            csrf_counter: 0,
+            httpclient: Client::builder()
+                .cookie_store(true)
+                .default_headers(default_headers)
+                .build().unwrap(),
+            revisions: HashMap::new(),
+            file_hashes: HashMap::new(),
+            report: PublishReport::default(),
+            retry_delay: Duration::from_secs(2),
+        }
+    }
+
+    /// Sends a request and turns API errors in the response body into a `MediaWikiError`
+    fn send(request: RequestBuilder) -> Result<Value, MediaWikiError> {
+        let response = request.send()?;
+        let retry_after = response.headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .map(Duration::from_secs);
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE {
+            return Err(MediaWikiError::RateLimited { retry_after });
+        }
+        let body = response.error_for_status()?.text()?;
+        let value: Value = serde_json::from_str(&body)
+            .map_err(|_| MediaWikiError::UnexpectedResponse(body.clone()))?;
+        if let Some(error) = value.get("error") {
+            return Err(MediaWikiError::from_api(error, retry_after));
+        }
+        Ok(value)
+    }
+
+    /// Runs `request` until it succeeds, refreshing the token or session and backing off as needed.
+    fn with_retry<T>(&mut self, mut request: impl FnMut(&mut Self) -> Result<T, MediaWikiError>) -> Result<T, MediaWikiError> {
+        let mut delay = self.retry_delay;
+        let mut attempt = 1;
+        loop {
+            let error = match request(self) {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            if attempt >= MAX_ATTEMPTS {
+                return Err(error);
+            }
+            match &error {
+                MediaWikiError::BadToken => {
+                    self.csrf_token = None;
+                }
+                MediaWikiError::SessionLost => {
+                    self.csrf_token = None;
+                    self.login()?;
+                }
+                MediaWikiError::RateLimited { retry_after } | MediaWikiError::MaxLag { retry_after } => {
+                    sleep(retry_after.unwrap_or(delay).max(delay));
+                    delay *= 2;
+                }
+                MediaWikiError::Http(e) if e.is_timeout() || e.is_connect() || e.status().is_some_and(|s| s.is_server_error()) => {
+                    sleep(delay);
+                    delay *= 2;
+                }
+                _ => return Err(error),
+            }
+            println!("Retrying after error: {error}");
+            attempt += 1;
+        }
+    }
+
+    pub fn login(&mut self) -> Result<(), MediaWikiError> {
+        let params = [("action", "query"), ("meta", "tokens"), ("type", "login"), ("format", "json")];
+        let response = Self::send(self.httpclient.get(self.url.as_str()).query(&params))?;
+        let response: MediaWikiResponse = serde_json::from_value(response)?;
+        let logintoken = response.query.tokens.get("logintoken")
+            .ok_or_else(|| MediaWikiError::UnexpectedResponse("missing login token".to_string()))?
+            .clone();
+        let params = [("action", "login"), ("lgname", self.botname.as_str()), ("lgpassword", self.botpass.as_str()), ("format", "json"), ("lgtoken", &*logintoken)];
+        let response = Self::send(self.httpclient.post(self.url.as_str()).form(&params))?;
+        match response["login"]["result"].as_str() {
+            Some("Success") => {
+                self.csrf_counter = 0;
+                Ok(())
+            }
+            _ => {
+                let reason = response["login"]["reason"].as_str().unwrap_or_default();
+                Err(MediaWikiError::LoginFailed(reason.to_string()))
+            }
+        }
+    }
+
+    pub fn csrf(&mut self) -> Result<String, MediaWikiError> {
+        // It's not clear how long the tokens last
+        if let Some(token) = &self.csrf_token {
+            if self.csrf_time.elapsed().as_secs() < 120 && self.csrf_counter < 128 {
+                return Ok(token.to_string());
+            }
+        }
+
+        if self.csrf_counter >= 128 {
+            self.login()?;
+        }
+
+        let params = [("action", "query"), ("meta", "tokens"), ("format", "json")];
+        let response = Self::send(self.httpclient.get(self.url.as_str()).query(&params))?;
+        let response: MediaWikiResponse = serde_json::from_value(response)?;
+        let token = response.query.tokens.get("csrftoken")
+            .ok_or_else(|| MediaWikiError::UnexpectedResponse("missing CSRF token".to_string()))?
+            .to_string();
+        self.csrf_token = Some(token.clone());
+        self.csrf_time = Instant::now();
+        Ok(token)
+    }
+
+    /// Fetches the latest content of each title, in batches of `REVISION_BATCH_SIZE` titles per request.
+    pub fn fetch_revisions(&mut self, titles: &[String]) -> Result<(), MediaWikiError> {
+        for batch in titles.chunks(REVISION_BATCH_SIZE) {
+            let joined = batch.join("|");
+            let response = self.with_retry(|client| {
+                let params = [
+                    ("action", "query"), ("prop", "revisions"), ("rvprop", "content|timestamp"), ("rvslots", "main"),
+                    ("curtimestamp", "1"), ("titles", joined.as_str()), ("format", "json"), ("formatversion", "2"),
+                ];
+                Self::send(client.httpclient.post(client.url.as_str()).form(&params))
+            })?;
+            let response: QueryResponse = serde_json::from_value(response)?;
+            if let Some(query) = response.query {
+                self.revisions.extend(parse_revisions(batch, query, response.curtimestamp));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetches the SHA-1 of the current version of each file, in batches of `REVISION_BATCH_SIZE`.
+    pub fn fetch_file_hashes(&mut self, filenames: &[String]) -> Result<(), MediaWikiError> {
+        for batch in filenames.chunks(REVISION_BATCH_SIZE) {
+            let titles: Vec<String> = batch.iter().map(|f| format!("File:{f}")).collect();
+            let joined = titles.join("|");
+            let response = self.with_retry(|client| {
+                let params = [
+                    ("action", "query"), ("prop", "imageinfo"), ("iiprop", "sha1"),
+                    ("titles", joined.as_str()), ("format", "json"), ("formatversion", "2"),
+                ];
+                Self::send(client.httpclient.post(client.url.as_str()).form(&params))
+            })?;
+            let response: QueryResponse = serde_json::from_value(response)?;
+            if let Some(query) = response.query {
+                for (title, hash) in parse_file_hashes(&titles, query) {
+                    self.file_hashes.insert(title.trim_start_matches("File:").to_string(), hash);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn try_upload(&mut self, upload: &FileUpload) -> Result<(), MediaWikiError> {
+        let response = self.with_retry(|client| {
+            let form = reqwest::blocking::multipart::Form::new()
+                .text("action", "upload")
+                .text("filename", upload.filename.clone())
+                .text("comment", upload.comment())
+                .text("text", upload.description())
+                .text("format", "json")
+                .text("maxlag", MAXLAG)
+                .text("assert", "user")
+                .text("token", client.csrf()?)
+                .text("ignorewarnings", "1")
+                .file("file", &upload.path)
+                .map_err(|e| MediaWikiError::UnexpectedResponse(e.to_string()))?;
+            client.csrf_counter += 1;
+            Self::send(client.httpclient.post(client.url.as_str()).multipart(form))
+        })?;
+        match response["upload"]["result"].as_str() {
+            Some("Success") => Ok(()),
+            _ => Err(MediaWikiError::Api { code: "uploadfailed".to_string(), info: response["upload"].to_string() }),
+        }
+    }
+
+    /// Saves `text`, returning the timestamp of the new revision (none if nothing changed).
+    ///
+    /// The edit is based on the revision that was fetched, so the wiki refuses it with an edit conflict if someone
+    /// edited the page since, or with `articleexists` if it was missing and has been created since.
+    fn try_edit(&mut self, title: &str, text: &str) -> Result<Option<String>, MediaWikiError> {
+        let summary = format!("Add/edit {}", title);
+        let fetched = self.revisions.get(title).cloned();
+        let response = self.with_retry(|client| {
+            let mut form = reqwest::blocking::multipart::Form::new()
+                .text("bot", "1")
+                .text("action", "edit")
+                .text("format", "json")
+                .text("maxlag", MAXLAG)
+                .text("assert", "user")
+                .text("title", title.to_string())
+                .text("text", text.to_string())
+                .text("summary", summary.clone())
+                .text("token", client.csrf()?);
+            if let Some(fetched) = &fetched {
+                if let Some(timestamp) = &fetched.timestamp {
+                    form = form.text("basetimestamp", timestamp.clone());
+                }
+                if let Some(fetched_at) = &fetched.fetched_at {
+                    form = form.text("starttimestamp", fetched_at.clone());
+                }
+                if fetched.content.is_none() {
+                    form = form.text("createonly", "1");
+                }
+            }
+            client.csrf_counter += 1;
+            Self::send(client.httpclient.post(client.url.as_str()).multipart(form))
+        })?;
+        match response["edit"]["result"].as_str() {
+            Some("Success") => Ok(response["edit"]["newtimestamp"].as_str().map(str::to_string)),
+            _ => Err(MediaWikiError::Api { code: "editfailed".to_string(), info: response["edit"].to_string() }),
+        }
+    }
+}
+
+/// Maps the pages in a query back to the titles that were requested, following any title normalisation.
+fn map_requested<T: Clone>(requested: &[String], normalized: &[NormalizedTitle], found: HashMap<String, T>) -> HashMap<String, T> {
+    let mut results = HashMap::new();
+    for title in requested {
+        let normalized = normalized.iter()
+            .find(|n| &n.from == title)
+            .map(|n| &n.to)
+            .unwrap_or(title);
+        if let Some(value) = found.get(normalized) {
+            results.insert(title.clone(), value.clone());
+        }
+    }
+    results
+}
+
+fn parse_revisions(requested: &[String], query: Query, fetched_at: Option<String>) -> HashMap<String, FetchedRevision> {
+    let mut contents = HashMap::new();
+    for page in query.pages {
+        let revision = match page.missing {
+            true => None,
+            false => page.revisions.first(),
+        };
+        contents.insert(page.title, FetchedRevision {
+            content: revision.and_then(|r| r.slots.get("main")).map(|slot| slot.content.clone()),
+            timestamp: revision.and_then(|r| r.timestamp.clone()),
+            fetched_at: fetched_at.clone(),
+        });
+    }
+    map_requested(requested, &query.normalized, contents)
+}
+
+/// SHA-1 of the current version of each requested `File:` title, `None` if there is no such file.
+fn parse_file_hashes(requested: &[String], query: Query) -> HashMap<String, Option<String>> {
+    let mut hashes = HashMap::new();
+    for page in query.pages {
+        hashes.insert(page.title, page.imageinfo.first().map(|info| info.sha1.clone()));
+    }
+    map_requested(requested, &query.normalized, hashes)
+}
+
+impl Publisher for MediaWikiClient {
+    fn upload(&mut self, upload: &FileUpload) {
+        let filename = &upload.filename;
+        let sha1 = match sha1_file(&upload.path) {
+            Ok(sha1) => sha1,
+            Err(error) => {
+                self.report.failed.push((filename.clone(), error.to_string()));
+                return;
+            }
+        };
+        if !self.file_hashes.contains_key(filename) {
+            if let Err(error) = self.fetch_file_hashes(std::slice::from_ref(filename)) {
+                println!("Could not fetch the hash of {filename}: {error}");
+            }
+        }
+        if self.file_hashes.get(filename).is_some_and(|current| current.as_ref() == Some(&sha1)) {
+            self.report.uploads_unchanged += 1;
+            return;
+        }
+        println!("Uploading {filename}");
+        match self.try_upload(upload) {
+            Ok(()) => {
+                self.report.uploaded += 1;
+                self.file_hashes.insert(filename.clone(), Some(sha1));
+            }
+            Err(error) => {
+                println!("Failed to upload {filename}: {error}");
+                self.report.failed.push((filename.clone(), error.to_string()));
+            }
+        }
+    }
+
+    fn add_edit_page(&mut self, title: &str, text: String) {
+        if title.trim() == "" {
+            panic!("Not editing page without title")
+        }
+        // a page that couldn't be fetched is not a missing page, so it is never overwritten blind
+        let current = match self.current_text(title) {
+            Ok(current) => current,
+            Err(error) => {
+                println!("Not editing {title}, could not fetch it: {error}");
+                self.report.failed.push((title.to_string(), error.to_string()));
+                return;
+            }
+        };
+        let outcome = EditOutcome::compare(current.as_deref(), &text);
+        if outcome == EditOutcome::Unchanged {
+            self.report.record(outcome);
+            return;
+        }
+        println!("Updating {title}");
+        match self.try_edit(title, &text) {
+            Ok(new_timestamp) => {
+                self.report.record(outcome);
+                let previous = self.revisions.remove(title);
+                let timestamp = new_timestamp.or_else(|| previous.as_ref().and_then(|p| p.timestamp.clone()));
+                let fetched_at = timestamp.clone().or_else(|| previous.and_then(|p| p.fetched_at));
+                self.revisions.insert(title.to_string(), FetchedRevision { content: Some(text), timestamp, fetched_at });
+            }
+            Err(error) => {
+                println!("Failed to edit {title}: {error}");
+                self.report.failed.push((title.to_string(), error.to_string()));
+            }
+        }
+    }
+
+    fn current_text(&mut self, title: &str) -> Result<Option<String>, MediaWikiError> {
+        if !self.revisions.contains_key(title) {
+            self.fetch_revisions(&[title.to_string()])?;
+        }
+        match self.revisions.get(title) {
+            Some(revision) => Ok(revision.content.clone()),
+            None => Err(MediaWikiError::UnexpectedResponse(format!("no revision of {title} in the response"))),
+        }
+    }
+
+    fn report(&mut self) -> &mut PublishReport {
+        &mut self.report
+    }
+
+    fn prefetch(&mut self, titles: &[String]) {
+        let missing: Vec<String> = titles.iter()
+            .filter(|t| !self.revisions.contains_key(*t))
+            .cloned()
+            .collect();
+        if let Err(error) = self.fetch_revisions(&missing) {
+            println!("Could not fetch current revisions: {error}");
+        }
+    }
+
+    fn prefetch_files(&mut self, filenames: &[String]) {
+        let missing: Vec<String> = filenames.iter()
+            .filter(|f| !self.file_hashes.contains_key(*f))
+            .cloned()
+            .collect();
+        if let Err(error) = self.fetch_file_hashes(&missing) {
+            println!("Could not fetch current file hashes: {error}");
+        }
+    }
+
+    fn finish(&mut self) {
+        println!("{}", self.report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::mockwiki::MockWiki;
+
+    use super::*;
+
+    #[test]
+    pub fn test_parse_revisions() {
+        let body = r#"{"batchcomplete":true,"query":{
+            "normalized":[{"fromencoded":false,"from":"racial_Modifiers","to":"Racial Modifiers"}],
+            "pages":[
+                {"ns":0,"title":"Missing page","missing":true},
+                {"pageid":2,"ns":0,"title":"Lorent","revisions":[{"timestamp":"2024-04-30T08:00:00Z","slots":{"main":{"contentmodel":"wikitext","content":"{{Country Detail}}"}}}]},
+                {"pageid":3,"ns":0,"title":"Racial Modifiers","revisions":[{"slots":{"main":{"content":"=== Elf ==="}}}]}
+            ]}}"#;
+        let response: QueryResponse = serde_json::from_str(body).unwrap();
+        let requested = vec!["Missing page".to_string(), "Lorent".to_string(), "racial_Modifiers".to_string()];
+        let revisions = parse_revisions(&requested, response.query.unwrap(), Some("2024-05-01T10:00:00Z".to_string()));
+        assert_eq!(revisions["Missing page"], FetchedRevision { content: None, timestamp: None, fetched_at: Some("2024-05-01T10:00:00Z".to_string()) });
+        assert_eq!(revisions["Lorent"].content.as_deref(), Some("{{Country Detail}}"));
+        assert_eq!(revisions["Lorent"].timestamp.as_deref(), Some("2024-04-30T08:00:00Z"));
+        assert_eq!(revisions["racial_Modifiers"].content.as_deref(), Some("=== Elf ==="));
+    }
+
+    #[test]
+    pub fn test_parse_file_hashes() {
+        let body = r#"{"batchcomplete":true,"query":{
+            "normalized":[{"fromencoded":false,"from":"File:A01_Flag.png","to":"File:A01 Flag.png"}],
+            "pages":[
+                {"ns":6,"title":"File:A01 Flag.png","imagerepository":"local","imageinfo":[{"sha1":"a9993e364706816aba3e25717850c26c9cd0d89d"}]},
+                {"ns":6,"title":"File:Gov placeholder.png","missing":true,"imagerepository":""}
+            ]}}"#;
+        let response: QueryResponse = serde_json::from_str(body).unwrap();
+        let requested = vec!["File:A01_Flag.png".to_string(), "File:Gov placeholder.png".to_string()];
+        let hashes = parse_file_hashes(&requested, response.query.unwrap());
+        assert_eq!(hashes.get("File:A01_Flag.png"), Some(&Some("a9993e364706816aba3e25717850c26c9cd0d89d".to_string())));
+        assert_eq!(hashes.get("File:Gov placeholder.png"), Some(&None));
+    }
+
+    #[test]
+    pub fn test_error_from_api() {
+        let error = |code: &str| serde_json::json!({"code": code, "info": "info"});
+        assert!(matches!(MediaWikiError::from_api(&error("badtoken"), None), MediaWikiError::BadToken));
+        assert!(matches!(MediaWikiError::from_api(&error("assertbotfailed"), None), MediaWikiError::SessionLost));
+        assert!(matches!(
+            MediaWikiError::from_api(&error("maxlag"), Some(Duration::from_secs(5))),
+            MediaWikiError::MaxLag { retry_after: Some(d) } if d == Duration::from_secs(5)
+        ));
+        assert!(matches!(MediaWikiError::from_api(&error("ratelimited"), None), MediaWikiError::RateLimited { retry_after: None }));
+        assert!(matches!(MediaWikiError::from_api(&error("protectedpage"), None), MediaWikiError::PermissionDenied { .. }));
+        assert!(matches!(MediaWikiError::from_api(&error("missingtitle"), None), MediaWikiError::Api { code, .. } if code == "missingtitle"));
+    }
+
+    #[test]
+    pub fn test_login_with_wrong_password() {
+        let wiki = MockWiki::start();
+        let mut client = MediaWikiClient::new(wiki.url.clone(), "Admin@wiki-bot".to_string(), "wrong".to_string());
+        assert!(matches!(client.login(), Err(MediaWikiError::LoginFailed(_))));
+    }
+
+    #[test]
+    pub fn test_edit_skips_unchanged_pages() {
+        let wiki = MockWiki::start();
+        wiki.set_page("Gawed", "{{Country Detail|tag=A02}}");
+        let mut client = wiki.client();
+        client.prefetch(&["Lorent".to_string(), "Gawed".to_string()]);
+        client.add_edit_page("Lorent", "{{Country Detail|tag=A01}}\n".to_string());
+        client.add_edit_page("Lorent", "{{Country Detail|tag=A01}}".to_string());
+        client.add_edit_page("Gawed", "{{Country Detail|tag=A02}}  \n".to_string());
+        client.redirect("Rósande", "Rosande");
+        assert_eq!(wiki.page("Lorent").unwrap(), "{{Country Detail|tag=A01}}");
+        assert_eq!(wiki.page("Rósande").unwrap(), "#REDIRECT [[Rosande]]");
+        assert_eq!(wiki.state().edits, 2);
+        assert_eq!((client.report.created, client.report.changed, client.report.unchanged), (2, 0, 2));
+    }
+
+    #[test]
+    pub fn test_revisions_are_fetched_in_batches() {
+        let wiki = MockWiki::start();
+        let titles: Vec<String> = (0..120).map(|i| format!("Page {i}")).collect();
+        for title in &titles {
+            wiki.set_page(title, "text");
+        }
+        let mut client = wiki.client();
+        let before = wiki.state().actions.len();
+        client.prefetch(&titles);
+        assert_eq!(wiki.state().actions.len() - before, 3);
+        for title in &titles {
+            client.add_edit_page(title, "text".to_string());
+        }
+        assert_eq!(wiki.state().actions.len() - before, 3);
+        assert_eq!(client.report.unchanged, 120);
+    }
+
+    #[test]
+    pub fn test_edit_recovers_from_bad_token_and_lost_session() {
+        let wiki = MockWiki::start();
+        let mut client = wiki.client();
+        client.add_edit_page("Lorent", "one".to_string());
+        wiki.state().expire_tokens();
+        client.add_edit_page("Lorent", "two".to_string());
+        wiki.state().expire_sessions();
+        client.add_edit_page("Lorent", "three".to_string());
+        assert_eq!(wiki.page("Lorent").unwrap(), "three");
+        assert_eq!(wiki.state().actions.iter().filter(|a| *a == "login").count(), 2);
+        assert!(client.report.failed.is_empty());
+    }
+
+    #[test]
+    pub fn test_edit_retries_on_maxlag_and_rate_limits() {
+        let wiki = MockWiki::start();
+        wiki.state().injected_errors.extend(["maxlag".to_string(), "ratelimited".to_string()]);
+        let mut client = wiki.client();
+        client.add_edit_page("Lorent", "text".to_string());
+        assert_eq!(wiki.page("Lorent").unwrap(), "text");
+        assert_eq!(client.report.created, 1);
+    }
+
+    #[test]
+    pub fn test_failed_edits_are_reported() {
+        let wiki = MockWiki::start();
+        wiki.state().injected_errors.push_back("protectedpage".to_string());
+        let mut client = wiki.client();
+        client.add_edit_page("Lorent", "text".to_string());
+        client.add_edit_page("Gawed", "text".to_string());
+        assert_eq!(wiki.page("Lorent"), None);
+        assert_eq!(wiki.page("Gawed").unwrap(), "text");
+        assert_eq!(client.report.failed.len(), 1);
+        assert_eq!(client.report.failed[0].0, "Lorent");
+        assert_eq!(client.report.created, 1);
+    }
+
+    #[test]
+    pub fn test_upload_skips_identical_files() {
+        let wiki = MockWiki::start();
+        let path = std::env::temp_dir().join("anbennar-wiki-test-upload.png");
+        fs::write(&path, [137, 80, 78, 71, 1]).unwrap();
+        let upload = FileUpload {
+            filename: "A01_Flag.png".to_string(),
+            path: path.clone(),
+            source: "gfx/flags/A01.tga".to_string(),
+            category: "Flags".to_string(),
+            commit: Some("abc123".to_string()),
+        };
+        let mut client = wiki.client();
+        client.prefetch_files(std::slice::from_ref(&upload.filename));
+        client.upload(&upload);
+        assert_eq!(wiki.state().uploads, 1);
+
+        let mut client = wiki.client();
+        client.prefetch_files(std::slice::from_ref(&upload.filename));
+        client.upload(&upload);
+        assert_eq!(wiki.state().uploads, 1);
+        assert_eq!(client.report.uploads_unchanged, 1);
+
+        fs::write(&path, [137, 80, 78, 71, 2]).unwrap();
+        client.upload(&upload);
+        assert_eq!(wiki.state().uploads, 2);
+        assert_eq!(wiki.state().files.get("File:A01 Flag.png").unwrap(), &vec![137, 80, 78, 71, 2]);
+        assert!(wiki.page("File:A01 Flag.png").unwrap().contains("[[Category:Flags]]"));
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    pub fn test_sections_against_mock_wiki() {
+        let wiki = MockWiki::start();
+        wiki.set_page("Monarchy", "Hand-written\n<!-- BEGIN BOT SECTION -->\nold\n<!-- END BOT SECTION -->\nMore prose");
+        wiki.set_page("Republic", "<!-- BEGIN BOT SECTION -->\nold");
+        let mut client = wiki.client();
+        client.add_edit_section("Monarchy", "new".to_string());
+        client.add_edit_section("Republic", "new".to_string());
+        assert!(wiki.page("Monarchy").unwrap().contains("\nnew\n<!-- END BOT SECTION -->\nMore prose"));
+        assert_eq!(wiki.page("Republic").unwrap(), "<!-- BEGIN BOT SECTION -->\nold");
+        assert_eq!(client.report.damaged_sections.len(), 1);
+    }
+
+    #[test]
+    pub fn test_failed_fetch_never_overwrites() {
+        let wiki = MockWiki::start();
+        wiki.set_page("Lorent", "Lore written by an editor");
+        wiki.state().injected_query_errors.extend(["readapidenied".to_string(), "readapidenied".to_string()]);
+        let mut client = wiki.client();
+        client.add_edit_page("Lorent", "generated".to_string());
+        client.add_edit_section("Lorent", "generated".to_string());
+        assert_eq!(wiki.page("Lorent").unwrap(), "Lore written by an editor");
+        assert_eq!(wiki.state().edits, 0);
+        assert_eq!(client.report.failed.iter().map(|(title, _)| title.as_str()).collect::<Vec<_>>(), vec!["Lorent", "Lorent"]);
+    }
+
+    #[test]
+    pub fn test_edits_since_the_fetch_conflict() {
+        let wiki = MockWiki::start();
+        wiki.set_page("Lorent", "old");
+        let mut client = wiki.client();
+        client.prefetch(&["Lorent".to_string(), "Gawed".to_string()]);
+        wiki.set_page("Lorent", "Edited by a human meanwhile");
+        wiki.set_page("Gawed", "Created by a human meanwhile");
+        client.add_edit_page("Lorent", "generated".to_string());
+        client.add_edit_page("Gawed", "generated".to_string());
+        assert_eq!(wiki.page("Lorent").unwrap(), "Edited by a human meanwhile");
+        assert_eq!(wiki.page("Gawed").unwrap(), "Created by a human meanwhile");
+        assert!(client.report.failed[0].1.contains("editconflict"));
+        assert!(client.report.failed[1].1.contains("articleexists"));
+
+        // the bot's own edits don't conflict with each other
+        client.add_edit_page("Rubyhold", "one".to_string());
+        client.add_edit_page("Rubyhold", "two".to_string());
+        assert_eq!(wiki.page("Rubyhold").unwrap(), "two");
+        assert_eq!(client.report.failed.len(), 2);
+    }
+}
@@ -1,11 +1,18 @@
 use std::collections::HashSet;
-use std::fs;
 
 use jomini::{TextTape, Windows1252Encoding};
 use jomini::text::ValueReader;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Default)]
+use crate::cache::ParseCache;
+use crate::paths::GamePaths;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct MissionTree {
+    /// The top level key, e.g. `lorent_1`
+    pub name: String,
+    /// Tags that can take this tree, from `tag`/`was_tag` in its potential
+    pub potential_tags: Vec<String>,
     pub generic: bool,
     pub ai: bool,
     pub has_country_shield: bool,
@@ -13,7 +20,7 @@ pub struct MissionTree {
     pub missions: Vec<Mission>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Mission {
     pub id: String,
     pub icon: Option<String>,
@@ -29,8 +36,9 @@ pub fn parse_mission_file(data: &[u8]) -> Vec<MissionTree> {
     let tape = TextTape::from_slice(data).unwrap();
     let reader = tape.windows1252_reader();
 
-    for (_key, _op, value) in reader.fields() {
+    for (key, _op, value) in reader.fields() {
         let mut tree = MissionTree{
+            name: key.read_string(),
             generic: false,
             ai: false,
             ..Default::default()
@@ -62,7 +70,11 @@ pub fn parse_mission_file(data: &[u8]) -> Vec<MissionTree> {
                             tree.has_country_shield = true;
                         }
                     }
-                    "potential" => {}
+                    "potential" => {
+                        let mut tags: Vec<String> = find_tags(HashSet::new(), value).into_iter().collect();
+                        tags.sort();
+                        tree.potential_tags = tags;
+                    }
                     "potential_on_load" => {}
                     _ => {
                         // should be a mission
@@ -115,52 +127,32 @@ pub fn parse_mission_file(data: &[u8]) -> Vec<MissionTree> {
     missions
 }
 
-pub fn tags_with_missions() -> HashSet<String> {
-    let mut tags = HashSet::new();
-    let paths = fs::read_dir("./anbennar/missions").expect("Missing missions directory");
+pub fn parse_missions(paths: &GamePaths, cache: &ParseCache) -> Vec<MissionTree> {
+    let files = paths.files_in("missions");
+    cache.parse_files("mission_trees", &files, parse_mission_file).into_iter().flatten().collect()
+}
 
-    fn recursively_find_tags(mut tags: HashSet<String>, obj: ValueReader<Windows1252Encoding>) -> HashSet<String> {
-        if let Ok(inner) = obj.read_object() {
-            for (key, _op, value) in inner.fields() {
-                if key.read_str() == "NOT" {
-                    continue;
-                }
-                if key.read_str() == "tag" || key.read_str() == "was_tag" {
-                    tags.insert(value.read_string().expect("could not parse as string"));
-                } else {
-                    tags = recursively_find_tags(tags.clone(), value);
-                }
+/// Every `tag` and `was_tag` in a trigger, except those under a NOT
+fn find_tags(mut tags: HashSet<String>, obj: ValueReader<Windows1252Encoding>) -> HashSet<String> {
+    if let Ok(inner) = obj.read_object() {
+        for (key, _op, value) in inner.fields() {
+            if key.read_str() == "NOT" {
+                continue;
             }
-        }
-        tags
-    }
-
-    for path in paths {
-        match path {
-            Ok(file) => {
-                let file = fs::read(file.path()).expect("error reading file");
-                let tape = TextTape::from_slice(file.as_slice()).unwrap();
-                let reader = tape.windows1252_reader();
-                for (_key, _op, value) in reader.fields() {
-                    if let Ok(mission_tree) = value.read_object() {
-                        for (key, _op, value) in mission_tree.fields() {
-                            let key = key.read_str();
-                            if key == "potential" {
-                                tags.extend(recursively_find_tags(HashSet::new(), value));
-                            }
-                        }
-                    }
-                }
+            if key.read_str() == "tag" || key.read_str() == "was_tag" {
+                tags.insert(value.read_string().expect("could not parse as string"));
+            } else {
+                tags = find_tags(tags.clone(), value);
             }
-            _ => {}
         }
     }
-
     tags
 }
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+
     use super::*;
 
     #[test]
@@ -174,26 +166,22 @@ mod tests {
 
     #[test]
     pub fn test_all_missions_parse() {
-        let paths = fs::read_dir("./anbennar/missions").expect("Missing missions directory");
-        for path in paths {
-            match path {
-                Ok(file) => {
-                    let data = fs::read(file.path()).expect("error reading file");
-                    let trees = parse_mission_file(data.as_slice());
-                    for tree in trees {
-                        for mission in tree.missions {
-                            assert!(!mission.id.is_empty())
-                        }
-                    }
+        for file in GamePaths::default().files_in("missions") {
+            let data = fs::read(file).expect("error reading file");
+            let trees = parse_mission_file(data.as_slice());
+            for tree in trees {
+                for mission in tree.missions {
+                    assert!(!mission.id.is_empty())
                 }
-                _ => {}
             }
         }
     }
 
     #[test]
     pub fn test_tags_with_missions() {
-        let actual = tags_with_missions();
+        let actual: Vec<String> = parse_missions(&GamePaths::default(), &ParseCache::disabled()).into_iter()
+            .flat_map(|tree| tree.potential_tags)
+            .collect();
         assert!(actual.contains(&"Z43".to_string()));
         assert!(actual.contains(&"U08".to_string()));
     }
@@ -0,0 +1,491 @@
+//! In-process stand-in for the parts of `api.php` the bot uses, so the client and the
+//! generators can be tested without a live wiki.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+
+use serde_json::{json, Value};
+
+use crate::mediawiki::MediaWikiClient;
+use crate::utils::sha1_hex;
+
+pub const BOT_NAME: &str = "Admin@wiki-bot";
+pub const BOT_PASS: &str = "bot-password";
+
+#[derive(Debug, Default)]
+pub struct WikiState {
+    /// Page text by normalised title, including `File:` description pages
+    pub pages: BTreeMap<String, String>,
+    /// Timestamp of each page's latest revision
+    pub timestamps: BTreeMap<String, String>,
+    /// Uploaded file contents by normalised `File:` title
+    pub files: BTreeMap<String, Vec<u8>>,
+    /// Number of edit and upload requests that changed something
+    pub edits: usize,
+    pub uploads: usize,
+    /// Every `action` received, in order
+    pub actions: Vec<String>,
+    /// Error codes to return for the next write requests (edit or upload)
+    pub injected_errors: VecDeque<String>,
+    /// Error codes to return for the next requests for page content
+    pub injected_query_errors: VecDeque<String>,
+    /// Revisions saved so far, the clock for timestamps
+    revision_count: usize,
+    sessions: Vec<String>,
+    csrf_tokens: Vec<String>,
+}
+
+impl WikiState {
+    /// Logs out every session, as if they had expired
+    pub fn expire_sessions(&mut self) {
+        self.sessions.clear();
+    }
+
+    /// Makes every issued CSRF token invalid
+    pub fn expire_tokens(&mut self) {
+        self.csrf_tokens.clear();
+    }
+
+    /// Saves a new revision of a page, returning its timestamp
+    pub fn save(&mut self, title: String, text: String) -> String {
+        self.revision_count += 1;
+        let timestamp = self.now();
+        self.pages.insert(title.clone(), text);
+        self.timestamps.insert(title, timestamp.clone());
+        timestamp
+    }
+
+    /// One second per revision saved, so every revision has its own timestamp
+    fn now(&self) -> String {
+        let seconds = self.revision_count;
+        format!("2024-01-01T{:02}:{:02}:{:02}Z", seconds / 3600 % 24, seconds / 60 % 60, seconds % 60)
+    }
+}
+
+pub struct MockWiki {
+    pub url: String,
+    state: Arc<Mutex<WikiState>>,
+    running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MockWiki {
+    pub fn start() -> MockWiki {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock wiki");
+        let address = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(WikiState::default()));
+        let running = Arc::new(AtomicBool::new(true));
+        let handle = {
+            let state = state.clone();
+            let running = running.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if !running.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        handle_connection(stream, &state);
+                    }
+                }
+            })
+        };
+        MockWiki {
+            url: format!("http://{address}/api.php"),
+            state,
+            running,
+            handle: Some(handle),
+        }
+    }
+
+    /// A client that is already logged in to this wiki and retries without waiting
+    pub fn client(&self) -> MediaWikiClient {
+        let mut client = MediaWikiClient::new(self.url.clone(), BOT_NAME.to_string(), BOT_PASS.to_string());
+        client.retry_delay = std::time::Duration::from_millis(1);
+        client.login().expect("could not log in to mock wiki");
+        client
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, WikiState> {
+        self.state.lock().unwrap()
+    }
+
+    pub fn page(&self, title: &str) -> Option<String> {
+        self.state().pages.get(&normalise_title(title)).cloned()
+    }
+
+    pub fn set_page(&self, title: &str, text: &str) {
+        self.state().save(normalise_title(title), text.to_string());
+    }
+}
+
+impl Drop for MockWiki {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        // wake up the listener so it notices it should stop
+        let _ = TcpStream::connect(self.url.trim_start_matches("http://").trim_end_matches("/api.php"));
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Title as MediaWiki stores it: underscores become spaces and the first letter is capitalised.
+pub fn normalise_title(title: &str) -> String {
+    let title = title.replace('_', " ");
+    let title = title.trim();
+    let (namespace, name) = match title.split_once(':') {
+        Some((namespace, name)) if ["File", "Category", "Template"].contains(&namespace) => (format!("{namespace}:"), name),
+        _ => (String::new(), title),
+    };
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) => format!("{namespace}{}{}", c.to_uppercase(), chars.as_str()),
+        None => namespace,
+    }
+}
+
+struct Request {
+    headers: HashMap<String, String>,
+    params: HashMap<String, String>,
+    files: HashMap<String, Vec<u8>>,
+}
+
+impl Request {
+    fn param(&self, name: &str) -> &str {
+        self.params.get(name).map(String::as_str).unwrap_or_default()
+    }
+
+    fn session(&self) -> Option<String> {
+        let cookies = self.headers.get("cookie")?;
+        cookies.split(';')
+            .filter_map(|c| c.trim().strip_prefix("mocksession="))
+            .map(str::to_string)
+            .next()
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, state: &Mutex<WikiState>) {
+    let request = match read_request(&mut stream) {
+        Some(request) => request,
+        None => return,
+    };
+    let mut headers = vec![("Content-Type".to_string(), "application/json; charset=utf-8".to_string())];
+    let body = {
+        let mut state = state.lock().unwrap();
+        handle_request(&request, &mut state, &mut headers)
+    };
+    let body = body.to_string();
+    let mut response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n", body.len());
+    for (name, value) in headers {
+        response += format!("{name}: {value}\r\n").as_str();
+    }
+    response += "\r\n";
+    response += body.as_str();
+    let _ = stream.write_all(response.as_bytes());
+}
+
+fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    let target = request_line.split_whitespace().nth(1)?.to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let mut body = vec![];
+    if let Some(length) = headers.get("content-length") {
+        body.resize(length.parse().ok()?, 0);
+        reader.read_exact(&mut body).ok()?;
+    } else if headers.get("transfer-encoding").is_some_and(|e| e.contains("chunked")) {
+        loop {
+            let mut size = String::new();
+            reader.read_line(&mut size).ok()?;
+            let size = usize::from_str_radix(size.trim(), 16).ok()?;
+            let mut chunk = vec![0; size + 2];
+            reader.read_exact(&mut chunk).ok()?;
+            if size == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..size]);
+        }
+    }
+
+    let mut params = HashMap::new();
+    let mut files = HashMap::new();
+    if let Some((_, query)) = target.split_once('?') {
+        params.extend(parse_urlencoded(query));
+    }
+    let content_type = headers.get("content-type").cloned().unwrap_or_default();
+    if content_type.starts_with("application/x-www-form-urlencoded") {
+        params.extend(parse_urlencoded(&String::from_utf8_lossy(&body)));
+    } else if let Some(boundary) = content_type.split("boundary=").nth(1) {
+        for (name, is_file, data) in parse_multipart(&body, boundary.trim_matches('"')) {
+            if is_file {
+                files.insert(name, data);
+            } else {
+                params.insert(name, String::from_utf8_lossy(&data).to_string());
+            }
+        }
+    }
+    Some(Request { headers, params, files })
+}
+
+fn parse_urlencoded(data: &str) -> Vec<(String, String)> {
+    data.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+fn percent_decode(data: &str) -> String {
+    let bytes = data.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Splits a multipart body into (field name, is a file, contents)
+fn parse_multipart(body: &[u8], boundary: &str) -> Vec<(String, bool, Vec<u8>)> {
+    let delimiter = format!("--{boundary}");
+    let mut parts = vec![];
+    for part in split_bytes(body, delimiter.as_bytes()).into_iter().skip(1) {
+        if part.starts_with(b"--") {
+            break;
+        }
+        let part = part.strip_prefix(b"\r\n").unwrap_or(part);
+        let header_end = match find_bytes(part, b"\r\n\r\n") {
+            Some(index) => index,
+            None => continue,
+        };
+        let headers = String::from_utf8_lossy(&part[..header_end]);
+        let data = &part[header_end + 4..];
+        let data = data.strip_suffix(b"\r\n").unwrap_or(data);
+        let disposition = headers.lines()
+            .find(|l| l.to_lowercase().starts_with("content-disposition"))
+            .unwrap_or_default();
+        let name = disposition.split("name=\"").nth(1)
+            .and_then(|n| n.split('"').next())
+            .unwrap_or_default()
+            .to_string();
+        parts.push((name, disposition.contains("filename="), data.to_vec()));
+    }
+    parts
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn split_bytes<'a>(mut data: &'a [u8], delimiter: &[u8]) -> Vec<&'a [u8]> {
+    let mut pieces = vec![];
+    while let Some(index) = find_bytes(data, delimiter) {
+        pieces.push(&data[..index]);
+        data = &data[index + delimiter.len()..];
+    }
+    pieces.push(data);
+    pieces
+}
+
+fn error(code: &str, info: &str) -> Value {
+    json!({"error": {"code": code, "info": info}})
+}
+
+fn handle_request(request: &Request, state: &mut WikiState, headers: &mut Vec<(String, String)>) -> Value {
+    let action = request.param("action").to_string();
+    state.actions.push(action.clone());
+    match action.as_str() {
+        "query" => query(request, state),
+        "login" => {
+            if request.param("lgname") == BOT_NAME && request.param("lgpassword") == BOT_PASS && request.param("lgtoken") == "logintoken+\\" {
+                let session = format!("session{}", state.sessions.len() + state.actions.len());
+                headers.push(("Set-Cookie".to_string(), format!("mocksession={session}; Path=/")));
+                state.sessions.push(session);
+                json!({"login": {"result": "Success", "lgusername": BOT_NAME}})
+            } else {
+                json!({"login": {"result": "Failed", "reason": "Incorrect username or password entered."}})
+            }
+        }
+        "edit" | "upload" => {
+            if request.param("assert") == "user" && !request.session().is_some_and(|s| state.sessions.contains(&s)) {
+                return error("assertuserfailed", "You are no longer logged in");
+            }
+            if !state.csrf_tokens.iter().any(|t| t == request.param("token")) {
+                return error("badtoken", "Invalid CSRF token.");
+            }
+            if let Some(code) = state.injected_errors.pop_front() {
+                if code == "maxlag" {
+                    headers.push(("Retry-After".to_string(), "0".to_string()));
+                }
+                return error(&code, "Injected error");
+            }
+            match action.as_str() {
+                "edit" => edit(request, state),
+                _ => upload(request, state),
+            }
+        }
+        _ => error("badvalue", "Unrecognized value for parameter \"action\"."),
+    }
+}
+
+fn edit(request: &Request, state: &mut WikiState) -> Value {
+    let title = normalise_title(request.param("title"));
+    let text = request.param("text").trim_end().to_string();
+    if request.param("createonly") == "1" && state.pages.contains_key(&title) {
+        return error("articleexists", "The article you tried to create has been created already.");
+    }
+    let base = request.param("basetimestamp");
+    if !base.is_empty() && state.timestamps.get(&title).is_some_and(|latest| latest != base) {
+        return error("editconflict", "Edit conflict.");
+    }
+    match state.pages.get(&title) == Some(&text) {
+        true => json!({"edit": {"result": "Success", "title": title, "nochange": ""}}),
+        false => {
+            state.edits += 1;
+            let timestamp = state.save(title.clone(), text);
+            json!({"edit": {"result": "Success", "title": title, "newtimestamp": timestamp}})
+        }
+    }
+}
+
+fn upload(request: &Request, state: &mut WikiState) -> Value {
+    let title = normalise_title(&format!("File:{}", request.param("filename")));
+    let data = match request.files.get("file") {
+        Some(data) => data.clone(),
+        None => return error("missingparam", "One of the parameters \"filekey\", \"file\" and \"url\" is required."),
+    };
+    if !state.pages.contains_key(&title) {
+        state.save(title.clone(), request.param("text").trim_end().to_string());
+    }
+    state.files.insert(title.clone(), data);
+    state.uploads += 1;
+    json!({"upload": {"result": "Success", "filename": title.trim_start_matches("File:")}})
+}
+
+fn query(request: &Request, state: &mut WikiState) -> Value {
+    if request.param("meta") == "tokens" {
+        return match request.param("type") {
+            "login" => json!({"batchcomplete": "", "query": {"tokens": {"logintoken": "logintoken+\\"}}}),
+            _ => {
+                let token = format!("csrf{}+\\", state.actions.len());
+                state.csrf_tokens.push(token.clone());
+                json!({"batchcomplete": "", "query": {"tokens": {"csrftoken": token}}})
+            }
+        };
+    }
+    match request.param("list") {
+        "allpages" => {
+            let prefix = request.param("apprefix");
+            let pages: Vec<Value> = state.pages.keys()
+                .filter(|t| !t.contains(':') && t.starts_with(prefix))
+                .map(|t| json!({"ns": 0, "title": t}))
+                .collect();
+            return json!({"batchcomplete": true, "query": {"allpages": pages}});
+        }
+        "categorymembers" => {
+            let category = format!("[[{}]]", normalise_title(request.param("cmtitle")));
+            let pages: Vec<Value> = state.pages.iter()
+                .filter(|(_, text)| text.contains(&category))
+                .map(|(t, _)| json!({"ns": if t.starts_with("File:") {6} else {0}, "title": t}))
+                .collect();
+            return json!({"batchcomplete": true, "query": {"categorymembers": pages}});
+        }
+        _ => {}
+    }
+
+    if request.param("prop").contains("revisions") {
+        if let Some(code) = state.injected_query_errors.pop_front() {
+            return error(&code, "Injected error");
+        }
+    }
+    let mut normalized = vec![];
+    let mut pages = vec![];
+    for title in request.param("titles").split('|').filter(|t| !t.is_empty()) {
+        let normal = normalise_title(title);
+        if normal != title {
+            normalized.push(json!({"fromencoded": false, "from": title, "to": normal}));
+        }
+        let mut page = json!({"ns": if normal.starts_with("File:") {6} else {0}, "title": normal});
+        match state.pages.get(&normal) {
+            None => {
+                page["missing"] = json!(true);
+            }
+            Some(text) => {
+                if request.param("prop").contains("revisions") {
+                    let timestamp = state.timestamps.get(&normal).cloned().unwrap_or_default();
+                    page["revisions"] = json!([{"timestamp": timestamp, "slots": {"main": {"contentmodel": "wikitext", "content": text}}}]);
+                }
+            }
+        }
+        if request.param("prop").contains("imageinfo") {
+            if let Some(data) = state.files.get(&normal) {
+                page["imagerepository"] = json!("local");
+                page["imageinfo"] = json!([{"sha1": sha1_hex(data)}]);
+            }
+        }
+        pages.push(page);
+    }
+    json!({"batchcomplete": true, "curtimestamp": state.now(), "query": {"normalized": normalized, "pages": pages}})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_normalise_title() {
+        assert_eq!(normalise_title("racial_Modifiers"), "Racial Modifiers");
+        assert_eq!(normalise_title("File:gov_placeholder.png"), "File:Gov placeholder.png");
+        assert_eq!(normalise_title("Lorent"), "Lorent");
+    }
+
+    #[test]
+    pub fn test_parse_multipart() {
+        let body = b"--xyz\r\nContent-Disposition: form-data; name=\"action\"\r\n\r\nedit\r\n--xyz\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.png\"\r\nContent-Type: image/png\r\n\r\n\x89PNG\r\n--xyz--\r\n";
+        let parts = parse_multipart(body, "xyz");
+        assert_eq!(parts, vec![
+            ("action".to_string(), false, b"edit".to_vec()),
+            ("file".to_string(), true, b"\x89PNG".to_vec()),
+        ]);
+    }
+
+    #[test]
+    pub fn test_percent_decode() {
+        assert_eq!(percent_decode("Lorent%7CGawed+Flag%2B"), "Lorent|Gawed Flag+");
+        assert_eq!(percent_decode("100%"), "100%");
+    }
+}
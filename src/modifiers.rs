@@ -1,5 +1,6 @@
 use std::string::String;
 
+use jomini::TextTape;
 use phf::phf_map;
 
 use crate::modifiers::ModifierFormat::{Flat, Percent};
@@ -82,6 +83,30 @@ pub fn localise_strings(description: &String, value: &String) -> (String, String
     }
 }
 
+/// A named block of modifiers from common/event_modifiers, in file order
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EventModifier {
+    pub id: String,
+    pub modifiers: Vec<(String, String)>,
+}
+
+pub fn parse_event_modifiers(data: &[u8]) -> Vec<EventModifier> {
+    let mut event_modifiers = vec![];
+    let tape = TextTape::from_slice(data).unwrap();
+    let reader = tape.windows1252_reader();
+    for (key, _op, value) in reader.fields() {
+        let mut event_modifier = EventModifier { id: key.read_string(), ..Default::default() };
+        if let Ok(modifiers) = value.read_object() {
+            for (key, _op, value) in modifiers.fields() {
+                if let Ok(value) = value.read_scalar() {
+                    event_modifier.modifiers.push((key.read_string(), value.to_string()));
+                }
+            }
+        }
+        event_modifiers.push(event_modifier);
+    }
+    event_modifiers
+}
 
 #[cfg(test)]
 mod tests {
@@ -132,6 +157,15 @@ mod tests {
         let localised = localise_strings(&"advisor_pool".to_string(), &"-1".to_string());
         assert_eq!(localised, ("Possible Advisors".to_string(), "-1".to_string()));
     }
+
+    #[test]
+    pub fn test_parse_event_modifiers() {
+        let parsed = parse_event_modifiers(b"elven_administration = {\n\tglobal_tax_modifier = 0.1\n\tpicture = \"elves\"\n}\n");
+        assert_eq!(parsed, vec![EventModifier {
+            id: "elven_administration".to_string(),
+            modifiers: vec![("global_tax_modifier".to_string(), "0.1".to_string()), ("picture".to_string(), "elves".to_string())],
+        }]);
+    }
 }
 
 
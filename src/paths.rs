@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use jomini::TextTape;
+
+use crate::config::PathsConfig;
+
+/// The mod's descriptor, read from the mod root for its `replace_path` entries
+pub const DESCRIPTOR_FILE: &str = "descriptor.mod";
+
+/// Where the game files live. Every parser reads through this instead of the working directory,
+/// so the tool can be pointed at any checkout of the mod or at a fixture tree in tests.
+///
+/// Files are resolved the way the game does it: the mod is laid over the base game, a mod file
+/// replaces the base game file with the same relative path, and directories listed as
+/// `replace_path` in the descriptor hide the base game's copy entirely.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GamePaths {
+    pub mod_root: PathBuf,
+    pub basegame_root: PathBuf,
+    /// Directories relative to the game root, e.g. `history/provinces`
+    pub replace_paths: Vec<String>,
+}
+
+impl Default for GamePaths {
+    fn default() -> Self {
+        GamePaths::from(&PathsConfig::default())
+    }
+}
+
+impl From<&PathsConfig> for GamePaths {
+    fn from(config: &PathsConfig) -> Self {
+        GamePaths::new(&config.mod_root, &config.basegame_root)
+    }
+}
+
+impl GamePaths {
+    pub fn new(mod_root: impl AsRef<Path>, basegame_root: impl AsRef<Path>) -> GamePaths {
+        let mod_root = mod_root.as_ref().to_path_buf();
+        let replace_paths = match fs::read(mod_root.join(DESCRIPTOR_FILE)) {
+            Ok(data) => parse_replace_paths(&data),
+            Err(_) => vec![],
+        };
+        GamePaths {
+            mod_root,
+            basegame_root: basegame_root.as_ref().to_path_buf(),
+            replace_paths,
+        }
+    }
+
+    /// `relative` inside the mod, e.g. `mod_file("map/area.txt")`
+    pub fn mod_file(&self, relative: impl AsRef<Path>) -> PathBuf {
+        self.mod_root.join(relative)
+    }
+
+    /// `relative` inside the base game
+    pub fn basegame_file(&self, relative: impl AsRef<Path>) -> PathBuf {
+        self.basegame_root.join(relative)
+    }
+
+    /// The path of `path` relative to the mod root, or None for files outside the mod
+    pub fn relative_to_mod(&self, path: &Path) -> Option<String> {
+        relative_to(path, &self.mod_root)
+    }
+
+    /// The path of `path` relative to the base game root, or None for files outside it
+    pub fn relative_to_basegame(&self, path: &Path) -> Option<String> {
+        relative_to(path, &self.basegame_root)
+    }
+
+    /// Whether the mod hides the base game's copy of `directory`
+    pub fn is_replaced(&self, directory: &str) -> bool {
+        let directory = normalise(directory);
+        self.replace_paths.iter().any(|replaced| {
+            directory == *replaced || directory.starts_with(&format!("{replaced}/"))
+        })
+    }
+
+    /// The file the game would load for `relative`: the mod's if it has one, otherwise the base game's
+    pub fn resolve(&self, relative: &str) -> Option<PathBuf> {
+        let mod_file = self.mod_file(relative);
+        if mod_file.is_file() {
+            return Some(mod_file);
+        }
+        let parent = Path::new(relative).parent().unwrap_or(Path::new(""));
+        if self.is_replaced(&parent.to_string_lossy()) {
+            return None;
+        }
+        Some(self.basegame_file(relative)).filter(|file| file.is_file())
+    }
+
+    /// The files directly inside `directory` in load order (by filename), merged from both roots
+    pub fn files_in(&self, directory: &str) -> Vec<PathBuf> {
+        let mut files = BTreeMap::new();
+        if !self.is_replaced(directory) {
+            for file in list_files(&self.basegame_file(directory)) {
+                files.insert(file.file_name().unwrap().to_os_string(), file);
+            }
+        }
+        for file in list_files(&self.mod_file(directory)) {
+            files.insert(file.file_name().unwrap().to_os_string(), file);
+        }
+        files.into_values().collect()
+    }
+
+    /// Every file under `directory` and its subdirectories, merged from both roots and sorted by relative path
+    pub fn files_in_recursive(&self, directory: &str) -> Vec<PathBuf> {
+        fn walk(root: &Path, relative: &str, found: &mut Vec<(String, PathBuf)>) {
+            let Ok(entries) = fs::read_dir(root.join(relative)) else { return };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let child = format!("{relative}/{name}");
+                if entry.path().is_dir() {
+                    walk(root, &child, found);
+                } else {
+                    found.push((child, entry.path()));
+                }
+            }
+        }
+
+        let directory = normalise(directory);
+        let mut files = BTreeMap::new();
+        let mut found = vec![];
+        walk(&self.basegame_root, &directory, &mut found);
+        for (relative, file) in found.drain(..) {
+            let parent = relative.rsplit_once('/').map(|(parent, _)| parent).unwrap_or("");
+            if !self.is_replaced(parent) {
+                files.insert(relative, file);
+            }
+        }
+        walk(&self.mod_root, &directory, &mut found);
+        files.extend(found);
+        files.into_values().collect()
+    }
+}
+
+/// The small mod tree under tests/fixtures, independent of the working directory
+#[cfg(test)]
+pub fn fixture_paths() -> GamePaths {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    GamePaths::new(fixtures.join("mod"), fixtures.join("basegame"))
+}
+
+fn relative_to(path: &Path, root: &Path) -> Option<String> {
+    path.strip_prefix(root).ok().map(|relative| relative.to_string_lossy().replace('\\', "/"))
+}
+
+fn normalise(directory: &str) -> String {
+    directory.replace('\\', "/").trim_matches('/').to_string()
+}
+
+fn list_files(directory: &Path) -> Vec<PathBuf> {
+    match fs::read_dir(directory) {
+        Ok(entries) => entries.flatten().map(|entry| entry.path()).filter(|path| path.is_file()).collect(),
+        Err(_) => vec![],
+    }
+}
+
+fn parse_replace_paths(data: &[u8]) -> Vec<String> {
+    let mut replace_paths = vec![];
+    let tape = match TextTape::from_slice(data) {
+        Ok(tape) => tape,
+        Err(_) => return replace_paths,
+    };
+    let reader = tape.windows1252_reader();
+    for (key, _op, value) in reader.fields() {
+        if key.read_str() == "replace_path" {
+            if let Ok(path) = value.read_string() {
+                replace_paths.push(normalise(&path));
+            }
+        }
+    }
+    replace_paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_game_paths() {
+        let paths = GamePaths::new("../anbennar-eu4", "/games/eu4");
+        assert_eq!(paths.mod_file("map/area.txt"), PathBuf::from("../anbennar-eu4/map/area.txt"));
+        assert_eq!(paths.basegame_file("gfx/interface"), PathBuf::from("/games/eu4/gfx/interface"));
+        let flag = paths.mod_file("gfx/flags/A01.tga");
+        assert_eq!(paths.relative_to_mod(&flag), Some("gfx/flags/A01.tga".to_string()));
+        assert_eq!(paths.relative_to_basegame(&flag), None);
+        assert_eq!(GamePaths::default().mod_root, PathBuf::from("./anbennar"));
+    }
+
+    #[test]
+    pub fn test_parse_replace_paths() {
+        let descriptor = b"name=\"Anbennar\"\nreplace_path=\"history/provinces\"\nreplace_path=\"common/bookmarks/\"\ntags={\n\t\"Alternative History\"\n}\n";
+        assert_eq!(parse_replace_paths(descriptor), vec!["history/provinces", "common/bookmarks"]);
+        let paths = fixture_paths();
+        assert_eq!(paths.replace_paths, vec!["history/countries"]);
+        assert!(paths.is_replaced("history/countries"));
+        assert!(paths.is_replaced("history/countries/old/"));
+        assert!(!paths.is_replaced("history"));
+        assert!(!paths.is_replaced("history/countries_extra"));
+    }
+
+    #[test]
+    pub fn test_files_in_overlay() {
+        let paths = fixture_paths();
+        let names = |files: Vec<PathBuf>| -> Vec<String> {
+            files.iter().map(|file| file.file_name().unwrap().to_string_lossy().to_string()).collect()
+        };
+
+        // base game files fall through and are loaded in filename order with the mod's
+        let tags = paths.files_in("common/country_tags");
+        assert_eq!(names(tags.clone()), vec!["00_countries.txt", "anb_countries.txt"]);
+        assert!(tags[0].starts_with(&paths.basegame_root));
+        assert!(tags[1].starts_with(&paths.mod_root));
+
+        // replace_path hides the base game's directory
+        assert_eq!(names(paths.files_in("history/countries")), vec!["A01 - Lorent.txt", "A02 - Deranne.txt"]);
+        assert_eq!(paths.resolve("history/countries/FRA - France.txt"), None);
+
+        // a mod file replaces the base game file with the same name
+        let icons = paths.files_in_recursive("gfx/interface/government_reform_icons");
+        let sources: Vec<String> = icons.iter()
+            .map(|icon| paths.relative_to_mod(icon).map(|p| format!("mod:{p}")).or_else(|| paths.relative_to_basegame(icon)).unwrap())
+            .collect();
+        assert_eq!(sources, vec![
+            "gfx/interface/government_reform_icons/legacy/theocracy.dds",
+            "mod:gfx/interface/government_reform_icons/monarchy.dds",
+            "gfx/interface/government_reform_icons/republic.dds",
+        ]);
+        assert_eq!(paths.resolve("common/country_tags/00_countries.txt"), Some(paths.basegame_file("common/country_tags/00_countries.txt")));
+        assert_eq!(paths.resolve("common/country_tags/missing.txt"), None);
+        assert!(paths.files_in("no/such/directory").is_empty());
+    }
+}
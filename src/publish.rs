@@ -0,0 +1,460 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::PathBuf;
+
+use regex::Regex;
+use serde::Serialize;
+
+use crate::mediawiki::MediaWikiError;
+use crate::sections::{merge_section, SectionError};
+use crate::utils::sha1_file;
+
+/// Destination for generated pages and images.
+///
+/// Generators only talk to this trait, so a run can either go to the live wiki
+/// ([`crate::mediawiki::MediaWikiClient`]) or to a local directory for review
+/// ([`DirectoryPublisher`]).
+pub trait Publisher {
+    fn add_edit_page(&mut self, title: &str, text: String);
+
+    /// Current text of a page, `None` if it does not exist.
+    fn current_text(&mut self, title: &str) -> Result<Option<String>, MediaWikiError>;
+
+    fn report(&mut self) -> &mut PublishReport;
+
+    /// Edits only the bot section of a page, leaving text that editors added around it alone.
+    fn add_edit_section(&mut self, title: &str, text: String) {
+        let current = match self.current_text(title) {
+            Ok(current) => current,
+            Err(error) => {
+                println!("Not editing {title}, could not fetch it: {error}");
+                self.report().failed.push((title.to_string(), error.to_string()));
+                return;
+            }
+        };
+        match merge_section(current.as_deref(), &text) {
+            Ok(merged) => self.add_edit_page(title, merged),
+            Err(error) => {
+                println!("Not editing {title}: {error}");
+                self.report().damaged_sections.push((title.to_string(), error));
+            }
+        }
+    }
+
+    /// Uploads a file unless the wiki already has a byte-identical version of it.
+    fn upload(&mut self, upload: &FileUpload);
+
+    fn redirect(&mut self, source_title: &str, target_title: &str) {
+        let text = format!("#REDIRECT [[{}]]", target_title);
+        self.add_edit_page(source_title, text);
+    }
+
+    /// Hint that these pages are about to be edited, so their current content can be fetched in bulk.
+    fn prefetch(&mut self, _titles: &[String]) {}
+
+    /// Hint that these files are about to be uploaded, so their current hashes can be fetched in bulk.
+    fn prefetch_files(&mut self, _filenames: &[String]) {}
+
+    /// Called once after all generators have run.
+    fn finish(&mut self) {}
+}
+
+/// A page built by a generator, not yet sent anywhere
+#[derive(Debug, Clone, PartialEq)]
+pub enum Page {
+    /// Replaces the whole page
+    Full { title: String, text: String },
+    /// Replaces only the bot section, see [`crate::sections`]
+    Section { title: String, text: String },
+    Redirect { title: String, target: String },
+}
+
+impl Page {
+    pub fn title(&self) -> &str {
+        match self {
+            Page::Full { title, .. } | Page::Section { title, .. } | Page::Redirect { title, .. } => title,
+        }
+    }
+
+    pub fn publish(self, client: &mut dyn Publisher) {
+        match self {
+            Page::Full { title, text } => client.add_edit_page(&title, text),
+            Page::Section { title, text } => client.add_edit_section(&title, text),
+            Page::Redirect { title, target } => client.redirect(&title, &target),
+        }
+    }
+}
+
+/// Publishes generated pages, letting the publisher fetch all of their current text at once first
+pub fn publish_pages(client: &mut dyn Publisher, pages: Vec<Page>) {
+    let titles: Vec<String> = pages.iter().map(|page| page.title().to_string()).collect();
+    client.prefetch(&titles);
+    for page in pages {
+        page.publish(client);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EditOutcome {
+    Created,
+    Changed,
+    Unchanged,
+}
+
+impl EditOutcome {
+    /// Compares the current page content (`None` if the page does not exist) with the new text.
+    pub fn compare(current: Option<&str>, text: &str) -> EditOutcome {
+        match current {
+            None => EditOutcome::Created,
+            Some(current) if normalise_text(current) == normalise_text(text) => EditOutcome::Unchanged,
+            Some(_) => EditOutcome::Changed,
+        }
+    }
+}
+
+/// An image to upload, along with where it came from for its description page.
+#[derive(Debug, Clone)]
+pub struct FileUpload {
+    pub filename: String,
+    pub path: PathBuf,
+    /// Path of the original file relative to the mod or base game root
+    pub source: String,
+    pub category: String,
+    /// Commit of the mod the file was taken from
+    pub commit: Option<String>,
+}
+
+impl FileUpload {
+    /// Text of the file description page
+    pub fn description(&self) -> String {
+        let mut text = format!("== Summary ==\nSource: <code>{}</code>", self.source);
+        if let Some(commit) = &self.commit {
+            text += format!(" (commit <code>{commit}</code>)").as_str();
+        }
+        text += format!("\n\n[[Category:{}]]\n", self.category).as_str();
+        text
+    }
+
+    pub fn comment(&self) -> String {
+        match &self.commit {
+            Some(commit) => format!("Upload {} from {} at {}", self.filename, self.source, commit),
+            None => format!("Upload {} from {}", self.filename, self.source),
+        }
+    }
+}
+
+/// Counts of what a run did to the pages it touched.
+#[derive(Debug, Default, PartialEq)]
+pub struct PublishReport {
+    pub created: usize,
+    pub changed: usize,
+    pub unchanged: usize,
+    /// Pages that were skipped because their bot section markers were damaged
+    pub damaged_sections: Vec<(String, SectionError)>,
+    pub uploaded: usize,
+    /// Files skipped because the wiki already has them with the same SHA-1
+    pub uploads_unchanged: usize,
+    /// Pages and files that could not be published, with the reason
+    pub failed: Vec<(String, String)>,
+}
+
+impl PublishReport {
+    pub fn record(&mut self, outcome: EditOutcome) {
+        match outcome {
+            EditOutcome::Created => self.created += 1,
+            EditOutcome::Changed => self.changed += 1,
+            EditOutcome::Unchanged => self.unchanged += 1,
+        }
+    }
+}
+
+impl Display for PublishReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Pages: {} created, {} changed, {} unchanged", self.created, self.changed, self.unchanged)?;
+        if self.uploaded + self.uploads_unchanged > 0 {
+            write!(f, "\nFiles: {} uploaded, {} unchanged", self.uploaded, self.uploads_unchanged)?;
+        }
+        if !self.damaged_sections.is_empty() {
+            write!(f, "\nSkipped {} pages with damaged bot section markers:", self.damaged_sections.len())?;
+            for (title, error) in &self.damaged_sections {
+                write!(f, "\n* {title}: {error}")?;
+            }
+        }
+        if !self.failed.is_empty() {
+            write!(f, "\nFailed to publish {}:", self.failed.len())?;
+            for (title, error) in &self.failed {
+                write!(f, "\n* {title}: {error}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// MediaWiki strips trailing whitespace when saving, so it must not count as a change.
+pub fn normalise_text(text: &str) -> String {
+    text.lines().map(|l| l.trim_end()).collect::<Vec<_>>().join("\n").trim_end().to_string()
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ManifestEntry {
+    Page { title: String, file: String },
+    Upload { filename: String, file: String, source: String, sha1: String, description: String },
+}
+
+/// Writes every page as `<title>.wikitext` and every upload under `files/`,
+/// along with a `manifest.json` describing what a live run would have published.
+pub struct DirectoryPublisher {
+    root: PathBuf,
+    manifest: Vec<ManifestEntry>,
+    report: PublishReport,
+}
+
+impl DirectoryPublisher {
+    pub fn new(root: PathBuf) -> DirectoryPublisher {
+        fs::create_dir_all(root.join("files")).expect("could not create output directory");
+        DirectoryPublisher {
+            root,
+            manifest: vec![],
+            report: PublishReport::default(),
+        }
+    }
+
+    fn write_manifest(&self) {
+        let manifest = serde_json::to_string_pretty(&self.manifest).unwrap();
+        fs::write(self.root.join("manifest.json"), manifest).expect("could not write manifest");
+    }
+}
+
+impl Publisher for DirectoryPublisher {
+    fn add_edit_page(&mut self, title: &str, text: String) {
+        if title.trim() == "" {
+            panic!("Not editing page without title")
+        }
+        let file = format!("{}.wikitext", file_safe_title(title));
+        let current = fs::read_to_string(self.root.join(&file)).ok();
+        let outcome = EditOutcome::compare(current.as_deref(), &text);
+        self.report.record(outcome);
+        if outcome != EditOutcome::Unchanged {
+            fs::write(self.root.join(&file), text).expect("could not write page");
+        }
+        self.manifest.retain(|e| !matches!(e, ManifestEntry::Page { title: t, .. } if t == title));
+        self.manifest.push(ManifestEntry::Page { title: title.to_string(), file });
+    }
+
+    fn current_text(&mut self, title: &str) -> Result<Option<String>, MediaWikiError> {
+        Ok(fs::read_to_string(self.root.join(format!("{}.wikitext", file_safe_title(title)))).ok())
+    }
+
+    fn report(&mut self) -> &mut PublishReport {
+        &mut self.report
+    }
+
+    fn upload(&mut self, upload: &FileUpload) {
+        let file = format!("files/{}", file_safe_title(&upload.filename));
+        let sha1 = sha1_file(&upload.path).expect("could not read upload");
+        if sha1_file(&self.root.join(&file)).is_ok_and(|existing| existing == sha1) {
+            self.report.uploads_unchanged += 1;
+        } else {
+            fs::copy(&upload.path, self.root.join(&file)).expect("could not copy upload");
+            self.report.uploaded += 1;
+        }
+        self.manifest.retain(|e| !matches!(e, ManifestEntry::Upload { filename: f, .. } if f == &upload.filename));
+        self.manifest.push(ManifestEntry::Upload {
+            filename: upload.filename.clone(),
+            file,
+            source: upload.source.clone(),
+            sha1,
+            description: upload.description(),
+        });
+    }
+
+    fn finish(&mut self) {
+        self.write_manifest();
+        println!("Wrote {} entries to {}", self.manifest.len(), self.root.display());
+        println!("{}", self.report);
+    }
+}
+
+/// Passes on only the pages and files whose title matches a pattern.
+pub struct TitleFilter {
+    inner: Box<dyn Publisher>,
+    pattern: Regex,
+}
+
+impl TitleFilter {
+    pub fn new(inner: Box<dyn Publisher>, pattern: Regex) -> TitleFilter {
+        TitleFilter { inner, pattern }
+    }
+}
+
+impl Publisher for TitleFilter {
+    fn add_edit_page(&mut self, title: &str, text: String) {
+        if self.pattern.is_match(title) {
+            self.inner.add_edit_page(title, text);
+        }
+    }
+
+    fn current_text(&mut self, title: &str) -> Result<Option<String>, MediaWikiError> {
+        self.inner.current_text(title)
+    }
+
+    fn report(&mut self) -> &mut PublishReport {
+        self.inner.report()
+    }
+
+    fn add_edit_section(&mut self, title: &str, text: String) {
+        if self.pattern.is_match(title) {
+            self.inner.add_edit_section(title, text);
+        }
+    }
+
+    fn upload(&mut self, upload: &FileUpload) {
+        if self.pattern.is_match(&upload.filename) {
+            self.inner.upload(upload);
+        }
+    }
+
+    fn prefetch(&mut self, titles: &[String]) {
+        let titles: Vec<String> = titles.iter().filter(|t| self.pattern.is_match(t)).cloned().collect();
+        self.inner.prefetch(&titles);
+    }
+
+    fn prefetch_files(&mut self, filenames: &[String]) {
+        let filenames: Vec<String> = filenames.iter().filter(|f| self.pattern.is_match(f)).cloned().collect();
+        self.inner.prefetch_files(&filenames);
+    }
+
+    fn finish(&mut self) {
+        self.inner.finish();
+    }
+}
+
+/// Page titles can contain characters that are not valid in file names.
+pub fn file_safe_title(title: &str) -> String {
+    title
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_file_safe_title() {
+        assert_eq!(file_safe_title("Lorent"), "Lorent");
+        assert_eq!(file_safe_title("File:A01 Flag.png"), "File_A01 Flag.png");
+        assert_eq!(file_safe_title("Tier 1/2"), "Tier 1_2");
+    }
+
+    #[test]
+    pub fn test_normalise_text() {
+        assert_eq!(normalise_text("a  \nb\t\n\n"), "a\nb");
+        assert_eq!(normalise_text("a\r\nb"), "a\nb");
+        assert_ne!(normalise_text("a\n\nb"), normalise_text("a\nb"));
+    }
+
+    #[test]
+    pub fn test_edit_outcome() {
+        let mut report = PublishReport::default();
+        assert_eq!(EditOutcome::compare(None, "text"), EditOutcome::Created);
+        assert_eq!(EditOutcome::compare(Some("text\n"), "text"), EditOutcome::Unchanged);
+        assert_eq!(EditOutcome::compare(Some("old"), "text"), EditOutcome::Changed);
+        report.record(EditOutcome::Created);
+        report.record(EditOutcome::Unchanged);
+        report.record(EditOutcome::Changed);
+        assert_eq!(report, PublishReport { created: 1, changed: 1, unchanged: 1, ..Default::default() });
+    }
+
+    #[test]
+    pub fn test_directory_publisher() {
+        let root = std::env::temp_dir().join("anbennar-wiki-test-directory-publisher");
+        let _ = fs::remove_dir_all(&root);
+        let mut publisher = DirectoryPublisher::new(root.clone());
+        publisher.add_edit_page("Lorent", "first".to_string());
+        publisher.add_edit_page("Lorent", "second".to_string());
+        publisher.redirect("Rósande", "Rosande");
+        let source = root.join("source.png");
+        fs::write(&source, [137, 80, 78, 71]).unwrap();
+        let upload = FileUpload {
+            filename: "A01_Flag.png".to_string(),
+            path: source,
+            source: "gfx/flags/A01.tga".to_string(),
+            category: "Flags".to_string(),
+            commit: Some("abc123".to_string()),
+        };
+        publisher.upload(&upload);
+        publisher.upload(&upload);
+        publisher.finish();
+
+        assert_eq!(fs::read_to_string(root.join("Lorent.wikitext")).unwrap(), "second");
+        assert_eq!(fs::read_to_string(root.join("Rósande.wikitext")).unwrap(), "#REDIRECT [[Rosande]]");
+        assert_eq!(fs::read(root.join("files/A01_Flag.png")).unwrap(), vec![137, 80, 78, 71]);
+        assert_eq!(publisher.manifest.len(), 3);
+        assert_eq!(publisher.report, PublishReport { created: 2, changed: 1, unchanged: 0, uploaded: 1, uploads_unchanged: 1, ..Default::default() });
+        let manifest: serde_json::Value = serde_json::from_str(&fs::read_to_string(root.join("manifest.json")).unwrap()).unwrap();
+        assert_eq!(manifest[0]["kind"], "page");
+        assert_eq!(manifest[0]["title"], "Lorent");
+        assert_eq!(manifest[2]["kind"], "upload");
+        assert_eq!(manifest[2]["file"], "files/A01_Flag.png");
+        assert_eq!(manifest[2]["source"], "gfx/flags/A01.tga");
+        assert_eq!(manifest[2]["description"], "== Summary ==\nSource: <code>gfx/flags/A01.tga</code> (commit <code>abc123</code>)\n\n[[Category:Flags]]\n");
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    pub fn test_title_filter() {
+        let root = std::env::temp_dir().join("anbennar-wiki-test-title-filter");
+        let _ = fs::remove_dir_all(&root);
+        let inner = Box::new(DirectoryPublisher::new(root.clone()));
+        let mut publisher = TitleFilter::new(inner, Regex::new("^Lor").unwrap());
+        publisher.add_edit_page("Lorent", "text".to_string());
+        publisher.add_edit_page("Gawed", "text".to_string());
+        publisher.add_edit_section("Lorenan", "text".to_string());
+        assert!(root.join("Lorent.wikitext").exists());
+        assert!(root.join("Lorenan.wikitext").exists());
+        assert!(!root.join("Gawed.wikitext").exists());
+        assert_eq!(publisher.report().created, 2);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    pub fn test_directory_publisher_sections() {
+        let root = std::env::temp_dir().join("anbennar-wiki-test-directory-publisher-sections");
+        let _ = fs::remove_dir_all(&root);
+        let mut publisher = DirectoryPublisher::new(root.clone());
+        publisher.add_edit_section("Lorent", "{{Country Detail}}".to_string());
+        let page = publisher.current_text("Lorent").unwrap().unwrap();
+        publisher.add_edit_page("Lorent", format!("{page}== Strategy ==\nHand-written\n"));
+        publisher.add_edit_section("Lorent", "{{Country Detail|tag=A01}}".to_string());
+        assert!(publisher.current_text("Lorent").unwrap().unwrap().ends_with("{{Country Detail|tag=A01}}\n<!-- END BOT SECTION -->\n== Strategy ==\nHand-written\n"));
+
+        publisher.add_edit_page("Gawed", "<!-- END BOT SECTION -->".to_string());
+        publisher.add_edit_section("Gawed", "{{Country Detail}}".to_string());
+        assert_eq!(publisher.current_text("Gawed").unwrap().unwrap(), "<!-- END BOT SECTION -->");
+        assert_eq!(publisher.report.damaged_sections, vec![("Gawed".to_string(), SectionError::MissingStart)]);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    pub fn test_publish_pages() {
+        let root = std::env::temp_dir().join("anbennar-wiki-test-publish-pages");
+        let _ = fs::remove_dir_all(&root);
+        let mut publisher = DirectoryPublisher::new(root.clone());
+        publish_pages(&mut publisher, vec![
+            Page::Full { title: "Countries".to_string(), text: "{| class=\"wikitable\"\n|}".to_string() },
+            Page::Section { title: "Lorent".to_string(), text: "{{Country Detail}}".to_string() },
+            Page::Redirect { title: "Rósande".to_string(), target: "Rosande".to_string() },
+        ]);
+        assert_eq!(publisher.current_text("Countries").unwrap().unwrap(), "{| class=\"wikitable\"\n|}");
+        assert!(publisher.current_text("Lorent").unwrap().unwrap().starts_with(crate::sections::SECTION_START));
+        assert_eq!(publisher.current_text("Rósande").unwrap().unwrap(), "#REDIRECT [[Rosande]]");
+        assert_eq!(publisher.report.created, 3);
+        let _ = fs::remove_dir_all(&root);
+    }
+}
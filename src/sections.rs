@@ -0,0 +1,99 @@
+use std::fmt::{Display, Formatter};
+
+pub const SECTION_START: &str = "<!-- BEGIN BOT SECTION: everything up to END BOT SECTION is regenerated automatically -->";
+pub const SECTION_END: &str = "<!-- END BOT SECTION -->";
+
+const START_PREFIX: &str = "<!-- BEGIN BOT SECTION";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SectionError {
+    MissingStart,
+    MissingEnd,
+    MultipleSections,
+    EndBeforeStart,
+}
+
+impl Display for SectionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SectionError::MissingStart => write!(f, "end marker without a start marker"),
+            SectionError::MissingEnd => write!(f, "start marker without an end marker"),
+            SectionError::MultipleSections => write!(f, "more than one bot section"),
+            SectionError::EndBeforeStart => write!(f, "end marker before the start marker"),
+        }
+    }
+}
+
+fn wrap(generated: &str) -> String {
+    format!("{SECTION_START}\n{}\n{SECTION_END}", generated.trim_end())
+}
+
+/// Replaces the bot-managed region of `current` with `generated`, keeping everything else.
+///
+/// A page without any markers gets a bot section at the top, and everything already on it is kept below.
+pub fn merge_section(current: Option<&str>, generated: &str) -> Result<String, SectionError> {
+    let current = match current {
+        Some(current) => current,
+        None => return Ok(format!("{}\n", wrap(generated))),
+    };
+
+    let starts: Vec<usize> = current.match_indices(START_PREFIX).map(|(i, _)| i).collect();
+    let ends: Vec<usize> = current.match_indices(SECTION_END).map(|(i, _)| i).collect();
+    match (starts.as_slice(), ends.as_slice()) {
+        ([], []) => Ok(format!("{}\n{current}", wrap(generated))),
+        ([], _) => Err(SectionError::MissingStart),
+        (_, []) => Err(SectionError::MissingEnd),
+        ([start], [end]) => {
+            if end < start {
+                return Err(SectionError::EndBeforeStart);
+            }
+            let after = end + SECTION_END.len();
+            Ok(format!("{}{}{}", &current[..*start], wrap(generated), &current[after..]))
+        }
+        _ => Err(SectionError::MultipleSections),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_merge_new_page() {
+        let merged = merge_section(None, "{{Country Detail}}\n").unwrap();
+        assert_eq!(merged, format!("{SECTION_START}\n{{{{Country Detail}}}}\n{SECTION_END}\n"));
+    }
+
+    #[test]
+    pub fn test_merge_page_without_markers() {
+        let merged = merge_section(Some("Lore written by an editor\n"), "{{Country Detail}}").unwrap();
+        assert_eq!(merged, format!("{SECTION_START}\n{{{{Country Detail}}}}\n{SECTION_END}\nLore written by an editor\n"));
+        // from then on only the section changes
+        let again = merge_section(Some(&merged), "{{Country Detail|tag=A01}}").unwrap();
+        assert_eq!(again, format!("{SECTION_START}\n{{{{Country Detail|tag=A01}}}}\n{SECTION_END}\nLore written by an editor\n"));
+    }
+
+    #[test]
+    pub fn test_merge_keeps_prose() {
+        let current = format!("Intro\n{SECTION_START}\nold\n{SECTION_END}\n== Strategy ==\nBe nice.\n");
+        let merged = merge_section(Some(&current), "new\n").unwrap();
+        assert_eq!(merged, format!("Intro\n{SECTION_START}\nnew\n{SECTION_END}\n== Strategy ==\nBe nice.\n"));
+        // running again is a no-op
+        assert_eq!(merge_section(Some(&merged), "new\n").unwrap(), merged);
+    }
+
+    #[test]
+    pub fn test_merge_damaged_markers() {
+        let only_start = format!("{SECTION_START}\nold\n");
+        assert_eq!(merge_section(Some(&only_start), "new"), Err(SectionError::MissingEnd));
+        let only_end = format!("old\n{SECTION_END}\n");
+        assert_eq!(merge_section(Some(&only_end), "new"), Err(SectionError::MissingStart));
+        let swapped = format!("{SECTION_END}\nold\n{SECTION_START}\n");
+        assert_eq!(merge_section(Some(&swapped), "new"), Err(SectionError::EndBeforeStart));
+        let twice = format!("{SECTION_START}\na\n{SECTION_END}\n{SECTION_START}\nb\n{SECTION_END}\n");
+        assert_eq!(merge_section(Some(&twice), "new"), Err(SectionError::MultipleSections));
+        // an editor shortening the start marker's explanation should not matter
+        let edited = format!("<!-- BEGIN BOT SECTION -->\nold\n{SECTION_END}");
+        assert_eq!(merge_section(Some(&edited), "new").unwrap(), wrap("new"));
+    }
+}
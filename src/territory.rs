@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::map::ProvinceHistory;
+
+pub const DEVELOPMENT_TITLE: &str = "Starting nations by development";
+
+/// What a country starts with, added up from the province histories
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Territory {
+    /// Province ids the country owns
+    pub owned: Vec<u64>,
+    /// Province ids the country has a core on, owned or not
+    pub cores: Vec<u64>,
+    pub base_tax: u64,
+    pub base_production: u64,
+    pub base_manpower: u64,
+    /// Trade good to the number of owned provinces producing it
+    pub trade_goods: BTreeMap<String, usize>,
+    /// Culture to the number of owned provinces with it
+    pub cultures: BTreeMap<String, usize>,
+    /// Religion to the number of owned provinces with it
+    pub religions: BTreeMap<String, usize>,
+}
+
+impl Territory {
+    pub fn development(&self) -> u64 {
+        self.base_tax + self.base_production + self.base_manpower
+    }
+}
+
+/// Every tag owning or with a core on a province, and what it starts with
+pub fn territories(histories: &BTreeMap<u64, ProvinceHistory>) -> BTreeMap<String, Territory> {
+    let mut territories: BTreeMap<String, Territory> = BTreeMap::new();
+    for (id, history) in histories {
+        for tag in &history.cores {
+            territories.entry(tag.clone()).or_default().cores.push(*id);
+        }
+        let Some(owner) = &history.owner else { continue };
+        let territory = territories.entry(owner.clone()).or_default();
+        territory.owned.push(*id);
+        territory.base_tax += history.base_tax.unwrap_or(0);
+        territory.base_production += history.base_production.unwrap_or(0);
+        territory.base_manpower += history.base_manpower.unwrap_or(0);
+        for (counts, key) in [
+            (&mut territory.trade_goods, &history.trade_goods),
+            (&mut territory.cultures, &history.culture),
+            (&mut territory.religions, &history.religion),
+        ] {
+            if let Some(key) = key {
+                *counts.entry(key.clone()).or_default() += 1;
+            }
+        }
+    }
+    territories
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::ParseCache;
+    use crate::map::parse_province_histories;
+    use crate::paths::fixture_paths;
+
+    use super::*;
+
+    #[test]
+    pub fn test_fixture_territories() {
+        let territories = territories(&parse_province_histories(&fixture_paths(), &ParseCache::disabled()));
+        assert_eq!(territories.keys().collect::<Vec<_>>(), vec!["A01", "A02"]);
+
+        let lorent = &territories["A01"];
+        assert_eq!(lorent.owned, vec![1, 2]);
+        // Deranne is claimed but not owned
+        assert_eq!(lorent.cores, vec![1, 2, 3]);
+        assert_eq!((lorent.base_tax, lorent.base_production, lorent.base_manpower), (15, 13, 10));
+        assert_eq!(lorent.development(), 38);
+        assert_eq!(lorent.trade_goods, BTreeMap::from([("grain".to_string(), 1), ("wine".to_string(), 1)]));
+        assert_eq!(lorent.cultures, BTreeMap::from([("high_lorentish".to_string(), 2)]));
+        assert_eq!(lorent.religions, BTreeMap::from([("regent_court".to_string(), 2)]));
+        assert_eq!(territories["A02"].development(), 14);
+    }
+}
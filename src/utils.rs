@@ -1,27 +1,13 @@
 use std::collections::BTreeSet;
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::Path;
 
 use jomini::json::{DuplicateKeyMode, JsonOptions};
 use jomini::text::ValueReader;
 use jomini::Windows1252Encoding;
 use serde_json::Value;
-
-pub fn read_all_files_in_path(directory: String) -> Vec<PathBuf> {
-    let mut results = Vec::new();
-    let paths = fs::read_dir(directory).expect("Missing directory");
-    for path in paths {
-        if let Ok(path) = path {
-            let path = path.path();
-            if path.is_dir() {
-                results.extend(read_all_files_in_path(path.to_str().unwrap().to_string()));
-            } else {
-                results.push(path)
-            }
-        }
-    }
-    results
-}
+use sha1::{Digest, Sha1};
 
 pub fn get_git_changed_files(directory: String, path_prefix: String, commit_range: String) -> BTreeSet<String> {
     let process = std::process::Command::new("git")
@@ -41,6 +27,82 @@ pub fn get_git_changed_files(directory: String, path_prefix: String, commit_rang
     BTreeSet::new()
 }
 
+pub fn get_git_commit(directory: String) -> Option<String> {
+    let process = std::process::Command::new("git")
+        .arg("rev-parse")
+        .arg("HEAD")
+        .current_dir(directory)
+        .output();
+    match process {
+        Ok(p) if p.status.success() => Some(String::from_utf8_lossy(&p.stdout).trim().to_string()),
+        _ => None
+    }
+}
+
+/// The full hash of `commit`, or None if the repository doesn't have it
+pub fn get_git_commit_hash(directory: &Path, commit: &str) -> Option<String> {
+    let process = std::process::Command::new("git")
+        .arg("rev-parse")
+        .arg("--verify")
+        .arg("--quiet")
+        .arg(format!("{commit}^{{commit}}"))
+        .current_dir(directory)
+        .output();
+    match process {
+        Ok(p) if p.status.success() => Some(String::from_utf8_lossy(&p.stdout).trim().to_string()),
+        _ => None
+    }
+}
+
+/// The content of `path` at `commit`, or None if it didn't exist then
+pub fn get_git_file(directory: &Path, commit: &str, path: &str) -> Option<Vec<u8>> {
+    let process = std::process::Command::new("git")
+        .arg("show")
+        .arg(format!("{commit}:{path}"))
+        .current_dir(directory)
+        .output();
+    match process {
+        Ok(p) if p.status.success() => Some(p.stdout),
+        _ => None
+    }
+}
+
+/// Writes the tree of `commit` to `destination`, replacing anything already there
+pub fn export_git_revision(directory: &Path, commit: &str, destination: &Path) -> io::Result<()> {
+    if destination.exists() {
+        fs::remove_dir_all(destination)?;
+    }
+    fs::create_dir_all(destination)?;
+    let mut archive = std::process::Command::new("git")
+        .arg("archive")
+        .arg("--format=tar")
+        .arg(commit)
+        .current_dir(directory)
+        .stdout(std::process::Stdio::piped())
+        .spawn()?;
+    let extracted = std::process::Command::new("tar")
+        .arg("-x")
+        .arg("-C")
+        .arg(destination)
+        .stdin(archive.stdout.take().expect("git archive output is piped"))
+        .status()?;
+    let archived = archive.wait()?;
+    match archived.success() && extracted.success() {
+        true => Ok(()),
+        false => Err(io::Error::other(format!("could not export {commit} from {}", directory.display()))),
+    }
+}
+
+/// Hex SHA-1 of a file, the same digest MediaWiki reports for uploaded files
+pub fn sha1_file(path: &Path) -> io::Result<String> {
+    let data = fs::read(path)?;
+    Ok(sha1_hex(&data))
+}
+
+pub fn sha1_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha1::digest(data))
+}
+
 pub fn jsonify(reader: ValueReader<Windows1252Encoding>) -> String {
     return reader
         .json()
@@ -117,12 +179,13 @@ pub fn translate(key: &str) -> &str {
 #[cfg(test)]
 mod tests {
     use crate::governments::parse_government_reforms;
+    use crate::paths::GamePaths;
 
     use super::*;
 
     #[test]
     pub fn test_htmlify() {
-        let reforms = parse_government_reforms(None);
+        let reforms = parse_government_reforms(&GamePaths::default(), None);
         for reform in reforms {
             if reform.potential.is_some() {
                 let potential = reform.potential.unwrap();
@@ -133,6 +196,11 @@ mod tests {
         }
     }
 
+    #[test]
+    pub fn test_sha1_hex() {
+        assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
     #[test]
     pub fn test_git_file_changes() {
         let files = get_git_changed_files(
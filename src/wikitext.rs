@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+
+use crate::graphics::{parse_sprites, text_icons};
+use crate::localisation::{colourise_as, ColourStyle};
+use crate::paths::GamePaths;
+
+/// Turns localised game text into wikitext: `£icon£` tokens become file embeds, `\n` becomes a line break and
+/// characters the wiki would read as markup are escaped, so the text is safe inside templates and tables.
+/// `§X` colour codes become spans or templates last, so they are never escaped.
+#[derive(Debug, Default, Clone)]
+pub struct Renderer {
+    /// Icon token to file name on the wiki, e.g. `adm` to `icon_adm.png`
+    pub icons: BTreeMap<String, String>,
+    pub colours: ColourStyle,
+}
+
+impl Renderer {
+    /// Icons from the text icon sprites of the game, with `overrides` replacing or adding tokens
+    pub fn load(paths: &GamePaths, overrides: &BTreeMap<String, String>) -> Renderer {
+        let mut icons = text_icons(&parse_sprites(paths));
+        icons.extend(overrides.iter().map(|(token, file)| (token.clone(), file.clone())));
+        Renderer { icons, colours: ColourStyle::default() }
+    }
+
+    pub fn render(&self, text: &str) -> String {
+        let mut output = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            let mut consumed = c.len_utf8();
+            match c {
+                '\\' if rest[1..].starts_with('n') => {
+                    output += "<br>";
+                    consumed = 2;
+                }
+                '\\' if rest[1..].starts_with(['"', '\\']) => {
+                    output.push_str(&rest[1..2]);
+                    consumed = 2;
+                }
+                '\n' => output += "<br>",
+                '£' => match self.icon(&rest[consumed..]) {
+                    Some((embed, length)) => {
+                        output += &embed;
+                        consumed += length;
+                    }
+                    None => output.push(c),
+                },
+                '|' => output += "&#124;",
+                '{' => output += "&#123;",
+                '}' => output += "&#125;",
+                '[' => output += "&#91;",
+                ']' => output += "&#93;",
+                '<' => output += "&lt;",
+                '>' => output += "&gt;",
+                // '' and ''' are italics and bold
+                '\'' if rest[1..].starts_with('\'') => output += "&#39;",
+                c => output.push(c),
+            }
+            rest = &rest[consumed..];
+        }
+        colourise_as(&output, self.colours)
+    }
+
+    /// The embed for a `£token£` whose opening `£` was just read, and how much of `rest` it used.
+    /// Tokens can have a frame after a bar, e.g. `£icon_religion|2£`, which is ignored.
+    fn icon(&self, rest: &str) -> Option<(String, usize)> {
+        let end = rest.find('£')?;
+        let token = &rest[..end];
+        if token.is_empty() || token.contains(char::is_whitespace) {
+            return None;
+        }
+        let name = token.split('|').next().unwrap_or_default();
+        let embed = match self.icons.get(name) {
+            Some(file) => format!("[[File:{file}|link=]]"),
+            // still readable, and easy to find on the wiki
+            None => format!("<code>{name}</code>"),
+        };
+        Some((embed, end + '£'.len_utf8()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::paths::fixture_paths;
+
+    use super::*;
+
+    #[test]
+    pub fn test_render() {
+        let mut renderer = Renderer { icons: BTreeMap::from([("adm".to_string(), "icon_adm.png".to_string())]), colours: ColourStyle::Span };
+        assert_eq!(renderer.render("£adm£ +1 a year\\nMore"), "[[File:icon_adm.png|link=]] +1 a year<br>More");
+        assert_eq!(renderer.render("£adm|2£ and £nothing£"), "[[File:icon_adm.png|link=]] and <code>nothing</code>");
+        assert_eq!(renderer.render("Costs 5 £ or less £"), "Costs 5 £ or less £");
+        assert_eq!(renderer.render("a | b {{c}} [[d]] <e> ''f''"), "a &#124; b &#123;&#123;c&#125;&#125; &#91;&#91;d&#93;&#93; &lt;e&gt; &#39;'f&#39;'");
+        assert_eq!(renderer.render("Say \\\"hi\\\"\nRósande's"), "Say \"hi\"<br>Rósande's");
+        assert_eq!(renderer.render("§Y<Rubyhold>§!\\n§Rx|y"), "<span class=\"yellow\">&lt;Rubyhold&gt;</span><br><span class=\"red\">x&#124;y</span>");
+        renderer.colours = ColourStyle::Template;
+        assert_eq!(renderer.render("§Gup §Wto§! 5§!"), "{{Color|green|up {{Color|white|to}} 5}}");
+    }
+
+    #[test]
+    pub fn test_load_icons() {
+        let overrides = BTreeMap::from([("magic".to_string(), "Magic icon.png".to_string())]);
+        let renderer = Renderer::load(&fixture_paths(), &overrides);
+        assert_eq!(renderer.render("£adm£ £magic£"), "[[File:icon_adm.png|link=]] [[File:Magic icon.png|link=]]");
+    }
+}
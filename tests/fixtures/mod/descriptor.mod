@@ -0,0 +1,3 @@
+name="Anbennar fixture"
+replace_path="history/countries"
+supported_version="1.37.*"